@@ -5,7 +5,6 @@
 //! read from 0x80000031 to read from stdin.
 
 // Yes, we have a lot of uses.
-extern crate mem;
 extern crate rose;
 
 #[macro_use]
@@ -13,87 +12,629 @@ extern crate clap;
 
 use rose::cpu::*;
 use rose::cpu::zpu::ZPU;
-use rose::bus::BusDevice;
+use rose::bus::Initiator;
 use rose::bus::memorybus::{MemoryBus32be, MemoryBusDevice32be};
-use rose::devices::memorybus::sio::SIOTerm;
+use rose::devices::memorybus::rtc::RTC;
+use rose::devices::memorybus::watchdog::{Watchdog, WatchdogAction};
+use rose::devices::memorybus::rng::{self, RNG};
+use rose::devices::memorybus::dma::DMA;
+use rose::devices::memorybus::rom::{ROM, WritePolicy};
+use rose::devices::memorybus::unmapped::{Unmapped, UnmappedPolicy};
+use rose::devices::memorybus::mmap::{MemMap, MapMode};
+use rose::devices::memorybus::exit::Exit;
+use rose::bus::shared::Shared;
+use rose::bus::mmu::MMU;
+use rose::bus::watch::{Watch, Watchpoints};
+use rose::bus::trace::Trace;
+use rose::clock::{self, TimeSource};
+use rose::config;
+use rose::devices::serial::{self, SerialBackend, Terminal, Buffer};
+use rose::platforms::{phi, zeta, zpuino};
+use rose::machine::{Machine, StopReason};
+use rose::expect::{Expect, Script};
+use rose::monitor::{self, Monitor, Action};
+use rose::debugger::{self, Debugger};
+use rose::num::parse_num;
 
 use rose::errors::*;
 
-use mem::MemoryBlock;
-use mem::MemoryCreator;
-use mem::std_impls::MemVector;
+use std::io::{self, BufReader, Read, Write};
+use std::fs::{self, File};
+use std::path::Path;
+use std::time::{Duration, Instant};
+use std::cmp;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
 
-use std::io::Read;
-use std::io::Write;
-use std::fs::File;
-
-use clap::{App, Arg};
+use clap::{App, Arg, ArgMatches};
 
 arg_enum!{
     #[derive(Debug)]
     enum Platforms {
         Phi,
-        Zeta
+        Zeta,
+        ZPUino
+    }
+}
+
+arg_enum!{
+    #[derive(Debug)]
+    enum Times {
+        Host,
+        Virtual
+    }
+}
+
+arg_enum!{
+    #[derive(Debug)]
+    enum WatchdogActions {
+        Reset,
+        Halt
+    }
+}
+
+arg_enum!{
+    #[derive(Debug)]
+    enum WritePolicies {
+        Ignore,
+        Fault,
+        Log
+    }
+}
+
+arg_enum!{
+    #[derive(Debug)]
+    enum UnmappedPolicies {
+        OpenBus,
+        Fault,
+        Log
     }
 }
 
+/// Clock frequency platforms report.
+const PLATFORM_MHZ: u32 = 1;
+
 fn main() {
     // Arg parsing
     let matches = App::new("rose-zpu")
         .version("0.1")
         .author("Adrian Pistol <vifino@tty.sh>")
         .about("ZPU test binary for ROSE.")
-        .arg(Arg::from_usage("<binary> 'The binary to load.'")
-             .required(true)
+        .arg(Arg::from_usage("[binary]... 'The binary to load, several with --test.'")
+             .required_unless_one(&["machine", "flash"])
              .index(1))
+        .arg(Arg::from_usage("-m, --machine=[FILE] 'Build the machine described in FILE, ignoring the other options.'"))
         .arg(Arg::from_usage("-p, --platform=[PLATFORM] 'The platform to emulate.'")
              .possible_values(&Platforms::variants())
+             .case_insensitive(true)
              .takes_value(true))
+        .arg(Arg::from_usage("--flash=[FILE] 'Contents of the SPI flash of ZPUino. Without a binary, the sketch in it gets booted.'"))
+        .arg(Arg::from_usage("--sketch-offset=[ADDR] 'Where the sketch starts in SPI flash.'"))
         .arg(Arg::from_usage("-e, --emulates=[BOOL] 'Use hardware EMULATE implementations'"))
+        .arg(Arg::from_usage("-r, --rom=[POLICY] 'Map the binary as ROM, treating writes to it according to POLICY.'")
+             .possible_values(&WritePolicies::variants())
+             .case_insensitive(true)
+             .takes_value(true))
+        .arg(Arg::from_usage("--ram-file=[FILE] 'Back RAM with FILE, keeping its contents across runs. The binary is still loaded into it, unless mapped as ROM.'"))
+        .arg(Arg::from_usage("-u, --unmapped=[POLICY] 'What accesses to unmapped addresses do. Unmapped reads are fatal by default.'")
+             .possible_values(&UnmappedPolicies::variants())
+             .case_insensitive(true)
+             .takes_value(true))
+        .arg(Arg::from_usage("--open-bus=[VALUE] 'Byte unmapped reads return, unless faulting.'"))
+        .arg(Arg::from_usage("-t, --time=[TIME] 'Time source of clocks, virtual time is derived from the instruction count.'")
+             .possible_values(&Times::variants())
+             .case_insensitive(true)
+             .takes_value(true))
+        .arg(Arg::from_usage("--rtc=[ADDR] 'Add a real time clock at ADDR.'"))
+        .arg(Arg::from_usage("--watchdog=[ADDR] 'Add a watchdog timer at ADDR.'"))
+        .arg(Arg::from_usage("--watchdog-timeout=[CYCLES] 'Initial watchdog timeout.'"))
+        .arg(Arg::from_usage("--watchdog-action=[ACTION] 'What an expired watchdog does.'")
+             .possible_values(&WatchdogActions::variants())
+             .case_insensitive(true)
+             .takes_value(true))
+        .arg(Arg::from_usage("--rng=[ADDR] 'Add a random number generator at ADDR.'"))
+        .arg(Arg::from_usage("--dma=[ADDR] 'Add a 4 channel DMA controller with access to RAM at ADDR.'"))
+        .arg(Arg::from_usage("--exit=[ADDR] 'Add an exit device at ADDR, writing an exit code to it ends the emulation.'"))
+        .arg(Arg::from_usage("--mmu=[ADDR] 'Put an MMU between CPU and bus, with its registers at ADDR.'"))
+        .arg(Arg::from_usage("--trace=[DEVICE]... 'Log accesses to the devices whose description starts with DEVICE, like sio or rtc. See devices in the monitor.'")
+             .number_of_values(1))
+        .arg(Arg::from_usage("--seed=[SEED] 'Seed the random number generator for reproducible runs, \"random\" picks one.'"))
+        .arg(Arg::from_usage("--test 'Run headless, with the console captured, reporting results as JSON, one line per binary.'"))
+        .arg(Arg::from_usage("--max-instructions=[N] 'Stop after N instructions.'"))
+        .arg(Arg::from_usage("--timeout=[SECONDS] 'Stop after SECONDS of wall clock time.'"))
+        .arg(Arg::from_usage("--expect-output=[FILE] 'With --test, console output to expect. Defaults to BINARY.expected, if it exists.'"))
+        .arg(Arg::from_usage("--expect-exit=[CODE] 'With --test, exit code to expect from the exit device.'"))
+        .arg(Arg::from_usage("--monitor 'Open the monitor at startup. Ctrl-A, then enter, opens it later on.'"))
+        .arg(Arg::from_usage("-b, --break=[ADDR]... 'Set a breakpoint at ADDR, opening the monitor there.'")
+             .number_of_values(1))
+        .arg(Arg::from_usage("--tui 'Run in a full screen debugger, with the console in a pane. Takes the size from the terminal, or COLUMNS and LINES.'"))
+        .arg(Arg::from_usage("--script=[FILE] 'With --test, drive the console with the expect script in FILE, then run to the end.'"))
         .get_matches();
 
-    let fname = matches.value_of("binary").unwrap();
+    let limits = Limits {
+        instructions: matches.value_of("max-instructions").map(|n| parse_num(n).unwrap_or_else(|e| ehandle(&e))),
+        timeout: matches.value_of("timeout").map(|t| Duration::from_secs(parse_num(t).unwrap_or_else(|e| ehandle(&e)))),
+    };
+    let binaries: Vec<&str> = matches.values_of("binary").map_or(vec![], |v| v.collect());
+
+    if matches.is_present("test") {
+        ::std::process::exit(test(&matches, &binaries, &limits));
+    }
+    if binaries.len() > 1 {
+        ehandle(&"several binaries need --test".into());
+    }
+
+    if matches.is_present("tui") {
+        return tui(&matches, binaries.get(0).cloned());
+    }
+
+    // Ctrl-A on the console pauses the machine, whether the guest reads it or not.
+    // The monitor reads its commands from the console too.
+    let paused = Arc::new(AtomicBool::new(false));
+    let terminal = Terminal::with_escape(serial::ESCAPE, paused.clone());
+    let mut input = BufReader::with_capacity(1, terminal.clone());
+    let console = Box::new(terminal);
+    let watch = Watchpoints::new();
+    let cpu = match matches.value_of("machine") {
+        Some(path) => config::load(path).and_then(|c| c.build_with(console, watch.clone())),
+        None => build(&matches, binaries.get(0).cloned(), console, watch.clone()),
+    }.unwrap_or_else(|e| ehandle(&e));
+    let mut machine = Machine::new(cpu).with_pause_handle(paused).with_watchpoints(watch.clone());
+    for addr in matches.values_of("break").map_or(vec![], |v| v.collect()) {
+        machine.add_breakpoint(parse_num(addr).unwrap_or_else(|e| ehandle(&e)));
+    }
+
+    machine.start().unwrap();
+
+    let mut monitor = Monitor::new().with_watchpoints(watch);
+    let mut open_monitor = matches.is_present("monitor");
+    loop {
+        if open_monitor {
+            machine.resume();
+            let action = monitor.repl(&mut machine, &mut input, &mut io::stdout()).unwrap_or_else(|e| ehandle(&e));
+            if action == Action::Quit {
+                return;
+            }
+        }
+        open_monitor = true;
+
+        match run(&mut machine, &limits, Some(&mut monitor)) {
+            Ended::Stopped(ref reason @ StopReason::Paused) |
+            Ended::Stopped(ref reason @ StopReason::Breakpoint(_)) |
+            Ended::Stopped(ref reason @ StopReason::Watchpoint(_)) => println!("\n{}", monitor::describe_stop(reason)),
+            ended => return finish(&machine, ended),
+        }
+    }
+}
+
+/// Run in the full screen debugger.
+fn tui(matches: &ArgMatches, binary: Option<&str>) {
+    let console = Buffer::new();
+    let watch = Watchpoints::new();
+    let cpu = match matches.value_of("machine") {
+        Some(path) => config::load(path).and_then(|c| c.build_with(Box::new(console.clone()), watch.clone())),
+        None => build(matches, binary, Box::new(console.clone()), watch.clone()),
+    }.unwrap_or_else(|e| ehandle(&e));
+    let mut machine = Machine::new(cpu).with_watchpoints(watch.clone());
+    for addr in matches.values_of("break").map_or(vec![], |v| v.collect()) {
+        machine.add_breakpoint(parse_num(addr).unwrap_or_else(|e| ehandle(&e)));
+    }
+    machine.start().unwrap();
+
+    let size = |var, default| ::std::env::var(var).ok().and_then(|v| v.parse().ok()).unwrap_or(default);
+    let (cols, rows) = debugger::terminal_size().unwrap_or_else(|| (size("COLUMNS", 80), size("LINES", 24)));
+    let mut debugger = Debugger::new(console).with_watchpoints(watch).with_size(cols, rows);
+    debugger.run(&mut machine, &mut io::stdout()).unwrap_or_else(|e| ehandle(&e));
+}
+
+/// Report how the run ended, exiting with a fitting status.
+fn finish(machine: &Machine, ended: Ended) {
+    match ended {
+        Ended::Stopped(StopReason::Fault(ref e)) => {
+            writeln!(::std::io::stderr(), "CPU stopped at PC {:#X}", machine.cpu().pc()).unwrap();
+            ehandle(e);
+        },
+        Ended::Stopped(StopReason::Exit(code)) => ::std::process::exit(code as i32),
+        Ended::Stopped(StopReason::Limit) => {
+            writeln!(::std::io::stderr(), "Instruction limit reached at PC {:#X}", machine.cpu().pc()).unwrap();
+            ::std::process::exit(1);
+        },
+        Ended::TimedOut => {
+            writeln!(::std::io::stderr(), "Timed out at PC {:#X}", machine.cpu().pc()).unwrap();
+            ::std::process::exit(1);
+        },
+        Ended::Stopped(_) => (),
+    }
+}
+
+/// Instructions run between checks of the limits.
+const CHUNK: u64 = 10000;
+
+struct Limits {
+    instructions: Option<u64>,
+    timeout: Option<Duration>,
+}
+
+enum Ended {
+    Stopped(StopReason),
+    TimedOut,
+}
+
+/// Run within `limits`, resetting the machine for watchdog resets.
+/// A monitor adds its watchpoints.
+fn run(machine: &mut Machine, limits: &Limits, mut monitor: Option<&mut Monitor>) -> Ended {
+    let start = Instant::now();
+    loop {
+        let chunk = match limits.instructions {
+            Some(max) if machine.cycles() >= max => return Ended::Stopped(StopReason::Limit),
+            Some(max) => cmp::min(CHUNK, max - machine.cycles()),
+            None => CHUNK,
+        };
+        let reason = match monitor {
+            Some(ref mut monitor) => monitor.run_for(machine, chunk),
+            None => machine.run_for(chunk),
+        };
+        match reason {
+            StopReason::Limit => {
+                if limits.timeout.map_or(false, |t| start.elapsed() >= t) {
+                    return Ended::TimedOut;
+                }
+            },
+            StopReason::Fault(ref e) if is_watchdog_reset(e) => {
+                if let ErrorKind::WatchdogReset(last_kick) = *e.kind() {
+                    writeln!(::std::io::stderr(), "Watchdog reset at PC {:#X}, last kicked at cycle {}", machine.cpu().pc(), last_kick).unwrap();
+                }
+                if let Err(e) = machine.reset() {
+                    return Ended::Stopped(StopReason::Fault(e));
+                }
+            },
+            reason => return Ended::Stopped(reason),
+        }
+    }
+}
+
+fn is_watchdog_reset(e: &Error) -> bool {
+    match *e.kind() {
+        ErrorKind::WatchdogReset(_) => true,
+        _ => false,
+    }
+}
+
+/// Run every binary, or the machine description, headless.
+/// Returns the exit status, 0 if all passed.
+fn test(matches: &ArgMatches, binaries: &[&str], limits: &Limits) -> i32 {
+    let expect_exit = matches.value_of("expect-exit").map(|c| parse_num(c).unwrap_or_else(|e| ehandle(&e)));
+    let script = matches.value_of("script").map(|path| Script::load(path).unwrap_or_else(|e| ehandle(&e)));
+    // Names and binaries, booting from flash without one.
+    let runs: Vec<(&str, Option<&str>)> = match (matches.value_of("machine"), matches.value_of("flash")) {
+        (Some(path), _) => vec![(path, None)],
+        (None, Some(flash)) if binaries.is_empty() => vec![(flash, None)],
+        _ => binaries.iter().map(|b| (*b, Some(*b))).collect(),
+    };
+
+    let mut failed = 0;
+    for &(name, binary) in &runs {
+        let expected = match matches.value_of("expect-output") {
+            Some(path) => Some(path.to_string()),
+            None => {
+                let path = format!("{}.expected", name);
+                if Path::new(&path).exists() { Some(path) } else { None }
+            },
+        };
+        let res = test_one(matches, name, binary, expected, expect_exit, script.as_ref(), limits);
+        if res.result != "pass" {
+            failed += 1;
+        }
+        println!("{}", res.to_json());
+    }
+    writeln!(::std::io::stderr(), "{} passed, {} failed", runs.len() - failed, failed).unwrap();
+    if failed > 0 { 1 } else { 0 }
+}
+
+struct TestResult {
+    name: String,
+    result: &'static str,
+    exit_code: Option<u32>,
+    instructions: u64,
+    message: String,
+}
+
+impl TestResult {
+    fn to_json(&self) -> String {
+        format!("{{\"name\":{},\"result\":\"{}\",\"exit_code\":{},\"instructions\":{},\"message\":{}}}",
+                json_str(&self.name), self.result,
+                self.exit_code.map_or("null".to_string(), |c| c.to_string()),
+                self.instructions, json_str(&self.message))
+    }
+}
+
+fn test_one(matches: &ArgMatches, name: &str, binary: Option<&str>, expected: Option<String>, expect_exit: Option<u32>, script: Option<&Script>, limits: &Limits) -> TestResult {
+    let mut res = TestResult {
+        name: name.to_string(),
+        result: "error",
+        exit_code: None,
+        instructions: 0,
+        message: String::new(),
+    };
+    let console = Buffer::new();
+    let built = match matches.value_of("machine") {
+        Some(path) => config::load(path).and_then(|c| c.build_with(Box::new(console.clone()), Watchpoints::new())),
+        None => build(matches, binary, Box::new(console.clone()), Watchpoints::new()),
+    };
+    let expected = match expected.map(|path| read_file(&path)) {
+        Some(Err(e)) => {
+            res.message = e.to_string();
+            return res;
+        },
+        Some(Ok(data)) => Some(data),
+        None => None,
+    };
+    let mut machine = match built.and_then(|cpu| {
+        let mut machine = Machine::new(cpu);
+        machine.start().map(|_| machine)
+    }) {
+        Ok(machine) => machine,
+        Err(e) => {
+            res.message = e.to_string();
+            return res;
+        },
+    };
+
+    // The script counts against the limits too.
+    let start = Instant::now();
+    if let Some(script) = script {
+        let done = {
+            let mut expect = Expect::new(&mut machine, console.clone());
+            expect.set_limit(limits.instructions);
+            expect.set_deadline(limits.timeout.map(|t| start + t));
+            script.run(&mut expect)
+        };
+        if let Err(e) = done {
+            res.instructions = machine.cycles();
+            let pc = machine.cpu().pc();
+            if limits.instructions.map_or(false, |max| res.instructions >= max) {
+                res.result = "limit";
+                res.message = format!("instruction limit reached at PC {:#X} running the script", pc);
+            } else if limits.timeout.map_or(false, |t| start.elapsed() >= t) {
+                res.result = "timeout";
+                res.message = format!("timed out at PC {:#X} running the script", pc);
+            } else {
+                res.result = "fail";
+                res.message = format!("script failed at PC {:#X}: {}", pc, error_chain_str(&e));
+            }
+            return res;
+        }
+    }
+
+    let left = Limits {
+        instructions: limits.instructions,
+        timeout: limits.timeout.map(|t| t.checked_sub(start.elapsed()).unwrap_or(Duration::from_secs(0))),
+    };
+    let ended = run(&mut machine, &left, None);
+    res.instructions = machine.cycles();
+    let pc = machine.cpu().pc();
+    res.result = "fail";
+    match ended {
+        Ended::Stopped(StopReason::Exit(code)) => res.exit_code = Some(code),
+        Ended::Stopped(StopReason::Fault(e)) => {
+            res.message = format!("fault at PC {:#X}: {}", pc, e);
+            return res;
+        },
+        Ended::Stopped(StopReason::Limit) => {
+            res.result = "limit";
+            res.message = format!("instruction limit reached at PC {:#X}", pc);
+            return res;
+        },
+        Ended::TimedOut => {
+            res.result = "timeout";
+            res.message = format!("timed out at PC {:#X}", pc);
+            return res;
+        },
+        Ended::Stopped(_) => (),
+    }
+
+    if let Some(want) = expect_exit {
+        if res.exit_code != Some(want) {
+            let got = res.exit_code.map_or("none".to_string(), |c| c.to_string());
+            res.message = format!("expected exit code {}, got {}", want, got);
+            return res;
+        }
+    } else if res.exit_code.map_or(false, |c| c != 0) {
+        res.message = "nonzero exit code".to_string();
+        return res;
+    }
+    if let Some(want) = expected {
+        let got = console.output();
+        if got != want {
+            let at = got.iter().zip(want.iter()).take_while(|&(a, b)| a == b).count();
+            res.message = format!("output differs from expected at byte {}", at);
+            return res;
+        }
+    }
+    res.result = "pass";
+    res
+}
+
+fn read_file(path: &str) -> Result<Vec<u8>, Error> {
+    let mut data = vec![];
+    File::open(path).chain_err(|| format!("failed to open {}", path))?.read_to_end(&mut data)?;
+    Ok(data)
+}
+
+/// An error with its causes, on one line.
+fn error_chain_str(e: &Error) -> String {
+    e.iter().map(|e| e.to_string()).collect::<Vec<_>>().join(": ")
+}
+
+/// Quote a string for JSON.
+fn json_str(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Build the machine from the command line options, loading `fname`,
+/// with `console` on the UART and `watch` on the bus.
+fn build(matches: &ArgMatches, fname: Option<&str>, console: Box<SerialBackend>, watch: Watchpoints) -> Result<Box<CPU>, Error> {
     let platform = value_t!(matches.value_of("platform"), Platforms).unwrap_or(Platforms::Phi);
     let use_emulates = value_t!(matches.value_of("emulates"), bool).unwrap_or(true);
+    let time = match value_t!(matches.value_of("time"), Times).unwrap_or(Times::Host) {
+        Times::Host => TimeSource::Host,
+        Times::Virtual => TimeSource::Virtual { epoch: clock::VIRTUAL_EPOCH, hz: clock::VIRTUAL_HZ },
+    };
+    let rom = value_t!(matches.value_of("rom"), WritePolicies).ok().map(|p| match p {
+        WritePolicies::Ignore => WritePolicy::Ignore,
+        WritePolicies::Fault => WritePolicy::Fault,
+        WritePolicies::Log => WritePolicy::Log,
+    });
+    let ram_file = matches.value_of("ram-file");
+    let open_bus = matches.value_of("open-bus").map_or(0, |v| parse_num(v).unwrap_or_else(|e| ehandle(&e)));
+    let unmapped = value_t!(matches.value_of("unmapped"), UnmappedPolicies).ok().map(|p| match p {
+        UnmappedPolicies::OpenBus => UnmappedPolicy::OpenBus(open_bus),
+        UnmappedPolicies::Fault => UnmappedPolicy::Fault,
+        UnmappedPolicies::Log => UnmappedPolicy::Log(open_bus),
+    });
+    let rtc = matches.value_of("rtc").map(|addr| parse_num(addr).unwrap_or_else(|e| ehandle(&e)));
+    let wdt = matches.value_of("watchdog").map(|addr| parse_num(addr).unwrap_or_else(|e| ehandle(&e)));
+    let wdt_timeout = value_t!(matches.value_of("watchdog-timeout"), u32).unwrap_or(1000000);
+    let wdt_action = match value_t!(matches.value_of("watchdog-action"), WatchdogActions).unwrap_or(WatchdogActions::Reset) {
+        WatchdogActions::Reset => WatchdogAction::Reset,
+        WatchdogActions::Halt => WatchdogAction::Halt,
+    };
+    let rng_addr = matches.value_of("rng").map(|addr| parse_num(addr).unwrap_or_else(|e| ehandle(&e)));
+    let dma = matches.value_of("dma").map(|addr| parse_num(addr).unwrap_or_else(|e| ehandle(&e)));
+    let exit = matches.value_of("exit").map(|addr| parse_num(addr).unwrap_or_else(|e| ehandle(&e)));
+    let mmu = matches.value_of("mmu").map(|addr| parse_num(addr).unwrap_or_else(|e| ehandle(&e)));
+    let seed = matches.value_of("seed").map(|s| {
+        let res = match s {
+            "random" => rng::random_seed(),
+            s => parse_num(s),
+        };
+        res.unwrap_or_else(|e| ehandle(&e))
+    });
 
-    // Platform variables
-    let uart = match platform {
-        Platforms::Phi =>  0x080a000c,
-        Platforms::Zeta => 0x80000024,
+    let flash = match (matches.value_of("flash"), &platform) {
+        (Some(path), &Platforms::ZPUino) => read_file(path)?,
+        (Some(_), _) => return Err("only ZPUino has SPI flash, use -p zpuino".into()),
+        (None, _) => vec![],
     };
+    let sketch_offset = matches.value_of("sketch-offset").map_or(zpuino::SKETCH_OFFSET, |addr| parse_num(addr).unwrap_or_else(|e| ehandle(&e)));
 
-    // Device init
-    let mut ram = Box::new(MemVector::new(0x80000));
-    let sio = Box::new(SIOTerm::new_zpu(uart)); // UART of the ZPU.
+    // Platform devices
+    let ram_size = match platform {
+        Platforms::Phi => phi::RAM_SIZE,
+        Platforms::Zeta => zeta::RAM_SIZE,
+        Platforms::ZPUino => zpuino::RAM_SIZE,
+    };
+    let mut devices: Vec<Box<MemoryBusDevice32be>> = match platform {
+        Platforms::Phi => phi::io(PLATFORM_MHZ, console),
+        Platforms::Zeta => zeta::io(console),
+        Platforms::ZPUino => zpuino::io(flash.clone(), console),
+    };
 
-    // Load rom.
-    let f = File::open(fname).unwrap();
-    let mut i = 0;
-    for byte in f.bytes() {
-        ram.set(i, byte.unwrap()).unwrap();
-        i += 1;
+    // Load rom, mapping the binary instead of copying it where possible.
+    let rom = match rom {
+        Some(policy) => {
+            let fname = fname.ok_or("mapping as ROM needs a binary")?;
+            let len = fs::metadata(fname)?.len() as usize;
+            if len == 0 {
+                return Err(format!("{} is empty, nothing to map as ROM", fname).into());
+            }
+            // Whole words, so the last one can be fetched.
+            let mem = MemMap::open(fname, (len + 3) & !3, MapMode::CopyOnWrite)?;
+            Some(ROM::new(0, mem, policy))
+        },
+        None => None,
+    };
+    let mut ram = match (ram_file, fname) {
+        (Some(path), _) => MemMap::open(path, ram_size, MapMode::Persistent),
+        (None, Some(fname)) if rom.is_none() => MemMap::open(fname, ram_size, MapMode::CopyOnWrite),
+        _ => MemMap::anon(ram_size),
+    }?;
+    if rom.is_none() {
+        match (ram_file, fname) {
+            (Some(_), Some(fname)) => load(fname, ram.as_mut_slice())?,
+            (_, None) => {
+                zpuino::boot(&flash, sketch_offset, ram.as_mut_slice())?;
+            },
+            _ => (),
+        }
     }
+    let ram = Shared::new(ram);
 
     // Set up bus
-    let devices: Vec<Box<MemoryBusDevice32be>> = vec![sio, ram];
-    let mut membus = Box::new(MemoryBus32be::new(devices));
-    membus.init().unwrap();
+    if let Some(rom) = rom {
+        devices.push(Box::new(rom));
+    }
+    if let Some(addr) = rtc {
+        devices.push(Box::new(RTC::new(addr, time)));
+    }
+    if let Some(addr) = wdt {
+        devices.push(Box::new(Watchdog::new(addr, wdt_timeout, wdt_action)));
+    }
+    if let Some(addr) = rng_addr {
+        let rng = match seed {
+            Some(seed) => RNG::new_seeded(addr, seed),
+            None => RNG::new_host(addr)?,
+        };
+        devices.push(Box::new(rng));
+    }
+    if let Some(addr) = exit {
+        devices.push(Box::new(Exit::new(addr)));
+    }
+    if let Some(addr) = dma {
+        let dma_bus = MemoryBus32be::new(vec![Box::new(ram.clone())]);
+        devices.push(Box::new(DMA::new(addr, 4, Box::new(Watch::new(dma_bus, watch.clone())))));
+    }
+    devices.push(Box::new(ram));
+    let initiator = Initiator::new();
+    if let Some(policy) = unmapped {
+        devices.push(Box::new(Unmapped::new(policy).with_initiator(initiator.clone())));
+    }
+    let traced: Vec<String> = matches.values_of("trace").map_or(vec![], |v| v.map(|d| d.to_lowercase()).collect());
+    let devices = devices.into_iter().map(|dev| -> Box<MemoryBusDevice32be> {
+        let desc = dev.describe().to_lowercase();
+        if traced.iter().any(|t| desc.starts_with(t)) {
+            Box::new(Trace::new(dev).with_initiator(initiator.clone()))
+        } else {
+            dev
+        }
+    }).collect();
+    let bus = Watch::new(MemoryBus32be::new(devices), watch).with_initiator(initiator.clone());
+    let mut membus: Box<MemoryBusDevice32be> = match mmu {
+        Some(addr) => Box::new(MMU::new(bus, addr).with_initiator(initiator.clone())),
+        None => Box::new(bus),
+    };
+    membus.init()?;
 
     // CPU
     let mut cpu = ZPU::new(membus, use_emulates);
-    cpu.sp = 0x80000;
-
-    cpu.start().unwrap();
+    cpu.sp = ram_size as u32;
+    cpu.set_initiator(initiator);
+    Ok(Box::new(cpu))
+}
 
-    while cpu.state() == CPUState::Running {
-        if let Err(ref e) = cpu.step() {
-            ehandle(e);
-        }
+/// Read the file at `path` into `mem`.
+fn load(path: &str, mem: &mut [u8]) -> Result<(), Error> {
+    let image = read_file(path)?;
+    if image.len() > mem.len() {
+        return Err(format!("{} doesn't fit into {:#X} bytes", path, mem.len()).into());
     }
+    mem[..image.len()].copy_from_slice(&image);
+    Ok(())
 }
 
 // Generic error_chain error handling
-fn ehandle(e: &Error) {
+fn ehandle(e: &Error) -> ! {
     println!("");
     let stderr = &mut ::std::io::stderr();
 
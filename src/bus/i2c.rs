@@ -0,0 +1,113 @@
+//! I2C bus.
+//!
+//! Slaves attach by their 7 bit address, a master drives transactions
+//! byte by byte: start, address, data, stop.
+//! Electrical details like clock stretching are not modelled.
+
+/// An I2C slave.
+pub trait I2CDevice {
+    /// Addressed by a start condition, `read` being the R/W bit.
+    /// `addr` is the address used, for devices that span several.
+    /// Returns whether the device acknowledged.
+    fn start(&mut self, addr: u8, read: bool) -> bool;
+
+    /// Master wrote a byte. Returns whether the device acknowledged.
+    fn write(&mut self, val: u8) -> bool;
+
+    /// Master reads a byte.
+    fn read(&mut self) -> u8;
+
+    /// Stop condition.
+    fn stop(&mut self) {}
+
+    /// Clock cycle, passed down from the master.
+    fn tick(&mut self) {}
+
+    /// Number of consecutive addresses the device answers to.
+    fn span(&self) -> u8 {
+        1
+    }
+}
+
+pub struct I2CBus {
+    devices: Vec<(u8, Box<I2CDevice>)>,
+    /// Index of the device addressed by the current transaction.
+    active: Option<usize>,
+    /// The master NACKed a read, the slave let go of the bus until the next start.
+    released: bool,
+}
+
+impl I2CBus {
+    pub fn new() -> I2CBus {
+        I2CBus {
+            devices: vec![],
+            active: None,
+            released: false,
+        }
+    }
+
+    /// Attach a slave at the 7 bit address `addr`.
+    pub fn attach(&mut self, addr: u8, dev: Box<I2CDevice>) {
+        self.devices.push((addr & 0x7F, dev));
+    }
+
+    /// Start (or repeated start) condition followed by an address byte.
+    /// Returns whether any slave acknowledged.
+    pub fn start(&mut self, addr: u8, read: bool) -> bool {
+        let addr = addr & 0x7F;
+        self.released = false;
+        if let Some(idx) = self.active.take() {
+            // Repeated start, the old transaction is over.
+            self.devices[idx].1.stop();
+        }
+        for (idx, &mut (base, ref mut dev)) in self.devices.iter_mut().enumerate() {
+            if addr >= base && addr < base.saturating_add(dev.span()) {
+                debug!("I2C: start {:#X} ({})", addr, if read { "read" } else { "write" });
+                if dev.start(addr, read) {
+                    self.active = Some(idx);
+                    return true;
+                }
+                return false;
+            }
+        }
+        debug!("I2C: no slave at {:#X}", addr);
+        false
+    }
+
+    /// Write a byte to the addressed slave. Returns the slave's ACK.
+    pub fn write(&mut self, val: u8) -> bool {
+        match self.active {
+            Some(idx) => self.devices[idx].1.write(val),
+            None => false,
+        }
+    }
+
+    /// Read a byte from the addressed slave, answering with `ack`.
+    /// After a NACK the slave stops sending until the next start.
+    /// Nobody driving the bus reads as 0xFF, like the pull-ups would.
+    pub fn read(&mut self, ack: bool) -> u8 {
+        let val = match self.active {
+            Some(idx) if !self.released => self.devices[idx].1.read(),
+            _ => 0xFF,
+        };
+        if !ack {
+            self.released = true;
+        }
+        val
+    }
+
+    /// Stop condition.
+    pub fn stop(&mut self) {
+        self.released = false;
+        if let Some(idx) = self.active.take() {
+            debug!("I2C: stop");
+            self.devices[idx].1.stop();
+        }
+    }
+
+    pub fn tick(&mut self) {
+        for &mut (_, ref mut dev) in self.devices.iter_mut() {
+            dev.tick();
+        }
+    }
+}
@@ -5,6 +5,9 @@
 // The bus itself also implements the same thing!
 // You could chain it if you wanted to!
 // Or, well, treat it like a normal mem::MemoryBlock!
+// Reads go to the devices in order, the first one to take it wins.
+// Writes go to every device taking them, except for catch-all devices
+// like unmapped space, which only get the writes nobody else took.
 // This time, it's a special 32 bit variant.
 // Yay!
 
@@ -16,7 +19,24 @@ extern crate mem;
 use self::mem::errors::{Error, ErrorKind};
 
 pub trait MemoryBusDevice: mem::MemoryBlock + super::BusDevice {}
-pub trait MemoryBusDevice32be: mem::MemoryBlock32be + super::BusDevice {}
+pub trait MemoryBusDevice32be: mem::MemoryBlock32be + super::BusDevice {
+    /// Devices behind this one, like those on a bus.
+    fn devices(&self) -> Vec<&MemoryBusDevice32be> {
+        vec![]
+    }
+
+    /// Does it take every address? Then buses only hand it writes no other device took.
+    fn catch_all(&self) -> bool {
+        false
+    }
+
+    /// Read a word without side effects, for debuggers looking at memory.
+    /// Memory answers like `get32be` does, other devices refuse,
+    /// as their reads might pop a FIFO or acknowledge something.
+    fn peek32be(&self, _addr: mem::Addr) -> Result<u32, Error> {
+        bail!(ErrorKind::NotApplicable("reads might have side effects"))
+    }
+}
 pub trait MemoryBusDevice32le: mem::MemoryBlock32le + super::BusDevice {}
 
 pub struct MemoryBus32be {
@@ -32,6 +52,29 @@ impl MemoryBus32be {
             devices: devs,
         }
     }
+
+    /// Hand a write to every device taking it, catch-all devices only if nobody did.
+    fn broadcast<F>(&mut self, mut write: F) -> Result<(), Error>
+        where F: FnMut(&mut Box<MemoryBusDevice32be>) -> Result<(), Error> {
+        for &catch_all in &[false, true] {
+            let mut taken = false;
+            for (i, dev) in self.devices.iter_mut().enumerate() {
+                if dev.catch_all() != catch_all {
+                    continue;
+                }
+                match write(dev) {
+                    Ok(()) => taken = true,
+                    Err(err) => {
+                        ehandlefatal(err, i + 1)?;
+                    },
+                }
+            }
+            if taken {
+                break;
+            }
+        }
+        Ok(())
+    }
 }
 impl MemoryBus32le {
     pub fn new(devs: Vec<Box<MemoryBusDevice32le>>) -> MemoryBus32le {
@@ -42,9 +85,16 @@ impl MemoryBus32le {
 }
 
 impl super::BusDevice for MemoryBus32be {
-    fn tick(&mut self) {
+    fn tick(&mut self) -> Result<(), RError> {
         for dev in self.devices.iter_mut() {
-            dev.tick()
+            dev.tick()?;
+        }
+        Ok(())
+    }
+
+    fn reset(&mut self) {
+        for dev in self.devices.iter_mut() {
+            dev.reset()
         }
     }
 
@@ -57,11 +107,26 @@ impl super::BusDevice for MemoryBus32be {
         }
         Ok(())
     }
+
+    fn interrupt(&self) -> bool {
+        self.devices.iter().any(|dev| dev.interrupt())
+    }
+
+    fn describe(&self) -> String {
+        format!("bus, {} devices", self.devices.len())
+    }
 }
 impl super::BusDevice for MemoryBus32le {
-    fn tick(&mut self) {
+    fn tick(&mut self) -> Result<(), RError> {
         for dev in self.devices.iter_mut() {
-            dev.tick()
+            dev.tick()?;
+        }
+        Ok(())
+    }
+
+    fn reset(&mut self) {
+        for dev in self.devices.iter_mut() {
+            dev.reset()
         }
     }
 
@@ -74,6 +139,14 @@ impl super::BusDevice for MemoryBus32le {
         }
         Ok(())
     }
+
+    fn interrupt(&self) -> bool {
+        self.devices.iter().any(|dev| dev.interrupt())
+    }
+
+    fn describe(&self) -> String {
+        format!("bus, {} devices", self.devices.len())
+    }
 }
 
 fn ehandlefatal(err: Error, pos: usize) -> Result<(), Error> {
@@ -103,18 +176,7 @@ impl mem::MemoryBlock for MemoryBus32be {
     }
 
     fn set(&mut self, addr: mem::Addr, val: mem::Byte) -> Result<(), Error> {
-        let mut pos = 1;
-        for dev in self.devices.iter_mut() {
-            let res = dev.set(addr, val);
-            match res {
-                Ok(()) => (),
-                Err(err) => {
-                    ehandlefatal(err, pos)?;
-                },
-            }
-            pos += 1;
-        }
-        Ok(())
+        self.broadcast(|dev| dev.set(addr, val))
     }
 
     fn get(&self, addr: mem::Addr) -> Result<mem::Byte, Error> {
@@ -171,18 +233,7 @@ impl mem::MemoryBlock for MemoryBus32be {
 
 impl mem::MemoryBlock32be for MemoryBus32be {
     fn set32be(&mut self, addr: mem::Addr, val: u32) -> Result<(), Error> {
-        let mut pos = 1;
-        for dev in self.devices.iter_mut() {
-            let res = dev.set32be(addr, val);
-            match res {
-                Ok(()) => (),
-                Err(err) => {
-                    ehandlefatal(err, pos)?;
-                },
-            }
-            pos += 1;
-        }
-        Ok(())
+        self.broadcast(|dev| dev.set32be(addr, val))
     }
 
     fn get32be(&self, addr: mem::Addr) -> Result<u32, Error> {
@@ -318,10 +369,39 @@ impl super::BusDevice for mem::MemoryBlock32be {}
 impl super::BusDevice for mem::MemoryBlock32le {}
 
 impl MemoryBusDevice for mem::MemoryBlock {}
-impl MemoryBusDevice32be for mem::MemoryBlock32be {}
+impl MemoryBusDevice32be for mem::MemoryBlock32be {
+    fn peek32be(&self, addr: mem::Addr) -> Result<u32, Error> {
+        self.get32be(addr)
+    }
+}
 impl MemoryBusDevice32le for mem::MemoryBlock32le {}
 
+impl MemoryBusDevice32be for MemoryBus32be {
+    fn devices(&self) -> Vec<&MemoryBusDevice32be> {
+        self.devices.iter().map(|dev| &**dev).collect()
+    }
+
+    fn peek32be(&self, addr: mem::Addr) -> Result<u32, Error> {
+        let mut pos = 1;
+        for dev in self.devices.iter() {
+            match dev.peek32be(addr) {
+                Ok(val) => { return Ok(val); },
+                Err(err) => {
+                    ehandlefatal(err, pos)?;
+                },
+            }
+            pos += 1;
+        }
+        bail!("no device answered a peek, tried {} devices in total", pos-1)
+    }
+}
+impl MemoryBusDevice32le for MemoryBus32le {}
+
 impl super::BusDevice for mem::std_impls::MemVector {}
 impl MemoryBusDevice for mem::std_impls::MemVector {}
-impl MemoryBusDevice32be for mem::std_impls::MemVector {}
+impl MemoryBusDevice32be for mem::std_impls::MemVector {
+    fn peek32be(&self, addr: mem::Addr) -> Result<u32, Error> {
+        mem::MemoryBlock32be::get32be(self, addr)
+    }
+}
 impl MemoryBusDevice32le for mem::std_impls::MemVector {}
@@ -0,0 +1,366 @@
+//! MMU.
+//!
+//! Optional address translation between a CPU and its bus.
+//! Walks two level page tables in guest memory, caching entries in a software TLB,
+//! and checks access permissions. Faults get recorded in the fault registers,
+//! and fail the access with a hardware fault, which stops the CPU.
+//! With `CTRL_TRAP` set, faults of the CPU are handed to the guest instead:
+//! the faulting instruction gets abandoned, and the MMU raises its interrupt
+//! until FAULT_CAUSE is written. Returning from the handler runs the instruction again.
+//! CPUs can't take traps while handling an interrupt, those faults still stop them.
+//!
+//! Pages are 4 KiB. A virtual address splits into 10 bits of page directory index,
+//! 10 bits of page table index and 12 bits of offset. Both page directory and
+//! page table entries are 32 bit big endian words: the upper 20 bits are the
+//! physical page number, the lower bits are `PTE_*` flags. Directory entries only
+//! need `PTE_VALID`.
+//!
+//! Instruction fetches are told apart by the `Initiator`, without one attached
+//! `PTE_EXEC` is not checked.
+//!
+//! Control registers, 32 bit big endian, at physical address `ctrl`,
+//! always reachable, whether translation is on or not:
+//!
+//! - `0x00` CTRL: `CTRL_*` bits.
+//! - `0x04` ROOT: physical address of the page directory. Writing flushes the TLB.
+//! - `0x08` FAULT_ADDR: read only, virtual address of the last fault.
+//! - `0x0C` FAULT_CAUSE: `FAULT_*` bits of the last fault. Writing acknowledges a trap.
+
+extern crate mem;
+
+use self::mem::errors::*;
+use errors::Error as RError;
+use bus::memorybus::{MemoryBusDevice, MemoryBusDevice32be};
+use bus::{BusDevice, Initiator};
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+
+const REG_CTRL: mem::Addr = 0x00;
+const REG_ROOT: mem::Addr = 0x04;
+const REG_FAULT_ADDR: mem::Addr = 0x08;
+const REG_FAULT_CAUSE: mem::Addr = 0x0C;
+const CTRL_SIZE: mem::Addr = 0x10;
+
+/// Translation on.
+pub const CTRL_ENABLE: u32 = 0x01;
+/// Write to flush the TLB.
+pub const CTRL_FLUSH: u32 = 0x02;
+/// Hand faults of the CPU to the guest, as an interrupt.
+pub const CTRL_TRAP: u32 = 0x04;
+
+pub const PTE_VALID: u32 = 0x01;
+pub const PTE_READ: u32 = 0x02;
+pub const PTE_WRITE: u32 = 0x04;
+pub const PTE_EXEC: u32 = 0x08;
+
+pub const FAULT_NOT_PRESENT: u32 = 0x01;
+pub const FAULT_READ: u32 = 0x02;
+pub const FAULT_WRITE: u32 = 0x04;
+pub const FAULT_EXEC: u32 = 0x08;
+
+const PAGE_MASK: u32 = 0xFFF;
+const TLB_SIZE: usize = 64;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Access {
+    Read,
+    Write,
+}
+
+pub struct MMU<T: MemoryBusDevice32be> {
+    phys: T,
+    ctrl_base: mem::Addr,
+    initiator: Option<Initiator>,
+
+    ctrl: u32,
+    root: u32,
+    fault_addr: Cell<u32>,
+    fault_cause: Cell<u32>,
+    /// A trap waits for the guest to acknowledge it.
+    trap: Cell<bool>,
+
+    /// Virtual page number to page table entry.
+    tlb: RefCell<HashMap<u32, u32>>,
+}
+
+impl<T: MemoryBusDevice32be> MMU<T> {
+    pub fn new(phys: T, ctrl_base: mem::Addr) -> MMU<T> {
+        MMU {
+            phys: phys,
+            ctrl_base: ctrl_base,
+            initiator: None,
+
+            ctrl: 0,
+            root: 0,
+            fault_addr: Cell::new(0),
+            fault_cause: Cell::new(0),
+            trap: Cell::new(false),
+
+            tlb: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Check execute permissions on fetches reported by `initiator`,
+    /// and deliver traps to the CPU through it.
+    pub fn with_initiator(mut self, initiator: Initiator) -> MMU<T> {
+        self.initiator = Some(initiator);
+        self
+    }
+
+    /// The physical bus.
+    pub fn phys(&mut self) -> &mut T {
+        &mut self.phys
+    }
+
+    /// Virtual address and cause of the last fault.
+    pub fn fault(&self) -> (u32, u32) {
+        (self.fault_addr.get(), self.fault_cause.get())
+    }
+
+    pub fn flush_tlb(&self) {
+        self.tlb.borrow_mut().clear();
+    }
+
+    fn enabled(&self) -> bool {
+        self.ctrl & CTRL_ENABLE != 0
+    }
+
+    fn fault_at(&self, va: u32, cause: u32) -> Error {
+        debug!("MMU: page fault at {:#X}, cause {:#X}", va, cause);
+        self.fault_addr.set(va);
+        self.fault_cause.set(cause);
+        if self.ctrl & CTRL_TRAP != 0 {
+            if let Some(ref i) = self.initiator {
+                if i.pc().is_some() {
+                    self.trap.set(true);
+                    i.raise_trap();
+                }
+            }
+        }
+        ErrorKind::HardwareFault(va as mem::Addr, "page fault").into()
+    }
+
+    /// Page table entry of `va`, from the TLB or the page tables.
+    fn lookup(&self, va: u32) -> Result<u32, Error> {
+        let vpn = va >> 12;
+        if let Some(pte) = self.tlb.borrow().get(&vpn) {
+            return Ok(*pte);
+        }
+        // Both tables come from the guest, entries running off the end wrap around.
+        let pde = self.phys.get32be(self.root.wrapping_add((va >> 22) * 4) as mem::Addr)?;
+        if pde & PTE_VALID == 0 {
+            return Err(self.fault_at(va, FAULT_NOT_PRESENT));
+        }
+        let pte = self.phys.get32be((pde & !PAGE_MASK).wrapping_add(((va >> 12) & 0x3FF) * 4) as mem::Addr)?;
+        if pte & PTE_VALID == 0 {
+            return Err(self.fault_at(va, FAULT_NOT_PRESENT));
+        }
+        let mut tlb = self.tlb.borrow_mut();
+        if tlb.len() >= TLB_SIZE {
+            tlb.clear();
+        }
+        tlb.insert(vpn, pte);
+        Ok(pte)
+    }
+
+    /// Physical address of `addr`.
+    fn translate(&self, addr: mem::Addr, access: Access) -> Result<mem::Addr, Error> {
+        if !self.enabled() {
+            return Ok(addr);
+        }
+        let va = addr as u32;
+        let pte = self.lookup(va)?;
+        let fetch = access == Access::Read && self.initiator.as_ref().map_or(false, |i| i.fetching());
+        let (needed, cause) = match access {
+            _ if fetch => (PTE_EXEC, FAULT_EXEC),
+            Access::Read => (PTE_READ, FAULT_READ),
+            Access::Write => (PTE_WRITE, FAULT_WRITE),
+        };
+        if pte & needed == 0 {
+            return Err(self.fault_at(va, cause));
+        }
+        Ok(((pte & !PAGE_MASK) | (va & PAGE_MASK)) as mem::Addr)
+    }
+
+    /// Physical address of `addr` for peeking: the page tables are peeked,
+    /// nothing gets cached and a fault only fails the peek.
+    fn peek_translate(&self, addr: mem::Addr) -> Result<mem::Addr, Error> {
+        if !self.enabled() {
+            return Ok(addr);
+        }
+        let va = addr as u32;
+        let cached = self.tlb.borrow().get(&(va >> 12)).cloned();
+        let pte = match cached {
+            Some(pte) => pte,
+            None => {
+                let pde = self.phys.peek32be(self.root.wrapping_add((va >> 22) * 4) as mem::Addr)?;
+                if pde & PTE_VALID == 0 {
+                    bail!(ErrorKind::InvalidAddr(addr));
+                }
+                self.phys.peek32be((pde & !PAGE_MASK).wrapping_add(((va >> 12) & 0x3FF) * 4) as mem::Addr)?
+            },
+        };
+        if pte & PTE_VALID == 0 || pte & PTE_READ == 0 {
+            bail!(ErrorKind::InvalidAddr(addr));
+        }
+        Ok(((pte & !PAGE_MASK) | (va & PAGE_MASK)) as mem::Addr)
+    }
+
+    /// Does a word at `addr` straddle pages?
+    fn straddles(&self, addr: mem::Addr) -> bool {
+        self.enabled() && (addr as u32 & PAGE_MASK) > PAGE_MASK - 3
+    }
+
+    fn ctrl_reg(&self, addr: mem::Addr) -> Option<mem::Addr> {
+        if addr >= self.ctrl_base && addr < self.ctrl_base + CTRL_SIZE {
+            return Some(addr - self.ctrl_base);
+        }
+        None
+    }
+
+    fn read_reg(&self, reg: mem::Addr) -> u32 {
+        match reg & !3 {
+            REG_CTRL => self.ctrl,
+            REG_ROOT => self.root,
+            REG_FAULT_ADDR => self.fault_addr.get(),
+            REG_FAULT_CAUSE => self.fault_cause.get(),
+            _ => 0,
+        }
+    }
+
+    fn write_reg(&mut self, reg: mem::Addr, val: u32) {
+        match reg & !3 {
+            REG_CTRL => {
+                if val & CTRL_FLUSH != 0 {
+                    self.flush_tlb();
+                }
+                self.ctrl = val & (CTRL_ENABLE | CTRL_TRAP);
+            },
+            REG_ROOT => {
+                self.root = val & !PAGE_MASK;
+                self.flush_tlb();
+            },
+            REG_FAULT_CAUSE => self.trap.set(false),
+            _ => (),
+        }
+    }
+}
+
+impl<T: MemoryBusDevice32be> mem::MemoryBlock for MMU<T> {
+    fn get_size(&self) -> usize {
+        self.phys.get_size()
+    }
+
+    fn set(&mut self, addr: mem::Addr, val: mem::Byte) -> Result<(), Error> {
+        if let Some(reg) = self.ctrl_reg(addr) {
+            // Registers take whole words only.
+            if reg & 3 == 3 {
+                self.write_reg(reg, val as u32);
+            }
+            return Ok(());
+        }
+        let pa = self.translate(addr, Access::Write)?;
+        self.phys.set(pa, val)
+    }
+
+    fn get(&self, addr: mem::Addr) -> Result<mem::Byte, Error> {
+        if let Some(reg) = self.ctrl_reg(addr) {
+            return Ok((self.read_reg(reg) >> ((3 - (reg & 3)) * 8)) as mem::Byte);
+        }
+        let pa = self.translate(addr, Access::Read)?;
+        self.phys.get(pa)
+    }
+
+    fn delete(&mut self, from: mem::Addr, to: mem::Addr) -> Result<(), Error> {
+        if self.enabled() {
+            bail!(ErrorKind::NotApplicable("delete with translation enabled"));
+        }
+        self.phys.delete(from, to)
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.phys.flush()
+    }
+}
+
+impl<T: MemoryBusDevice32be> mem::MemoryBlock32be for MMU<T> {
+    fn set32be(&mut self, addr: mem::Addr, val: u32) -> Result<(), Error> {
+        if let Some(reg) = self.ctrl_reg(addr) {
+            self.write_reg(reg, val);
+            return Ok(());
+        }
+        if self.straddles(addr) {
+            for i in 0..4 {
+                mem::MemoryBlock::set(self, addr + i, (val >> ((3 - i) * 8)) as mem::Byte)?;
+            }
+            return Ok(());
+        }
+        let pa = self.translate(addr, Access::Write)?;
+        self.phys.set32be(pa, val)
+    }
+
+    fn get32be(&self, addr: mem::Addr) -> Result<u32, Error> {
+        if let Some(reg) = self.ctrl_reg(addr) {
+            return Ok(self.read_reg(reg));
+        }
+        if self.straddles(addr) {
+            let mut val = 0;
+            for i in 0..4 {
+                val = (val << 8) | mem::MemoryBlock::get(self, addr + i)? as u32;
+            }
+            return Ok(val);
+        }
+        let pa = self.translate(addr, Access::Read)?;
+        self.phys.get32be(pa)
+    }
+}
+
+impl<T: MemoryBusDevice32be> BusDevice for MMU<T> {
+    fn describe(&self) -> String {
+        format!("MMU, registers at {:#X}", self.ctrl_base)
+    }
+
+    fn tick(&mut self) -> Result<(), RError> {
+        self.phys.tick()
+    }
+
+    fn init(&mut self) -> Result<(), RError> {
+        self.phys.init()
+    }
+
+    fn reset(&mut self) {
+        self.ctrl = 0;
+        self.root = 0;
+        self.trap.set(false);
+        self.flush_tlb();
+        self.phys.reset()
+    }
+
+    fn interrupt(&self) -> bool {
+        self.trap.get() || self.phys.interrupt()
+    }
+}
+impl<T: MemoryBusDevice32be> MemoryBusDevice for MMU<T> {}
+impl<T: MemoryBusDevice32be> MemoryBusDevice32be for MMU<T> {
+    fn devices(&self) -> Vec<&MemoryBusDevice32be> {
+        vec![&self.phys]
+    }
+
+    fn peek32be(&self, addr: mem::Addr) -> Result<u32, Error> {
+        if let Some(reg) = self.ctrl_reg(addr) {
+            return Ok(self.read_reg(reg));
+        }
+        if self.straddles(addr) {
+            let mut val = 0;
+            for i in 0..4 {
+                let pa = self.peek_translate(addr + i)?;
+                let word = self.phys.peek32be(pa & !3)?;
+                val = (val << 8) | ((word >> ((3 - (pa & 3)) * 8)) & 0xFF);
+            }
+            return Ok(val);
+        }
+        let pa = self.peek_translate(addr)?;
+        self.phys.peek32be(pa)
+    }
+}
@@ -2,16 +2,90 @@
 
 // Modules
 pub mod memorybus;
+pub mod shared;
+pub mod i2c;
+pub mod mmu;
+pub mod watch;
+pub mod trace;
 
 use errors::*;
 
+use std::rc::Rc;
+use std::cell::Cell;
+
 // Common things
 pub trait BusDevice {
     /// Do whatever in a clock cycle.
-    fn tick(&mut self) {}
+    ///
+    /// An error stops the CPU before it runs the next instruction.
+    fn tick(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Reset to power-on state.
+    fn reset(&mut self) {}
 
     /// Initialize.
     fn init(&mut self) -> Result<(), Error> {
         Ok(())
     }
+
+    /// Is the device asserting its interrupt line?
+    fn interrupt(&self) -> bool {
+        false
+    }
+
+    /// What the device is and where, for listing devices.
+    fn describe(&self) -> String {
+        String::from("device")
+    }
+}
+
+/// Who is accessing the bus.
+///
+/// A CPU with one attached sets its PC before running an instruction
+/// and clears it while clocking the devices, so devices can tell
+/// accesses of the CPU from those of, say, a DMA controller.
+/// It also flags instruction fetches, and devices flag failed
+/// accesses the CPU should take as a trap instead of stopping.
+#[derive(Clone, Default)]
+pub struct Initiator {
+    pc: Rc<Cell<Option<u32>>>,
+    fetching: Rc<Cell<bool>>,
+    trap: Rc<Cell<bool>>,
+}
+
+impl Initiator {
+    pub fn new() -> Initiator {
+        Initiator::default()
+    }
+
+    /// PC of the instruction accessing the bus, if a CPU is.
+    pub fn pc(&self) -> Option<u32> {
+        self.pc.get()
+    }
+
+    pub fn set_pc(&self, pc: Option<u32>) {
+        self.pc.set(pc)
+    }
+
+    /// Is the CPU fetching an instruction?
+    pub fn fetching(&self) -> bool {
+        self.fetching.get()
+    }
+
+    pub fn set_fetching(&self, fetching: bool) {
+        self.fetching.set(fetching)
+    }
+
+    /// Ask the CPU to abandon the instruction whose access failed and take an interrupt,
+    /// running it again after that, rather than stopping on the error.
+    pub fn raise_trap(&self) {
+        self.trap.set(true)
+    }
+
+    /// Was a trap raised? Clears it.
+    pub fn take_trap(&self) -> bool {
+        self.trap.replace(false)
+    }
 }
@@ -0,0 +1,123 @@
+//! Shared devices.
+//!
+//! Once a device is boxed and handed to a bus, the host loses access to it.
+//! `Shared` wraps a device in an `Rc<RefCell<_>>`, so a clone can be put on
+//! the bus while the host keeps another one around to poke at it.
+
+extern crate mem;
+
+use self::mem::errors::Error;
+use errors::Error as RError;
+use bus::BusDevice;
+use bus::memorybus::{MemoryBusDevice, MemoryBusDevice32be, MemoryBusDevice32le};
+
+use std::rc::Rc;
+use std::cell::{RefCell, Ref, RefMut};
+
+pub struct Shared<T: ?Sized> {
+    inner: Rc<RefCell<T>>,
+}
+
+impl<T> Shared<T> {
+    pub fn new(dev: T) -> Shared<T> {
+        Shared {
+            inner: Rc::new(RefCell::new(dev)),
+        }
+    }
+}
+
+impl<T: ?Sized> Shared<T> {
+    /// Borrow the device.
+    pub fn borrow(&self) -> Ref<T> {
+        self.inner.borrow()
+    }
+
+    /// Borrow the device mutably.
+    pub fn borrow_mut(&self) -> RefMut<T> {
+        self.inner.borrow_mut()
+    }
+}
+
+impl<T: ?Sized> Clone for Shared<T> {
+    fn clone(&self) -> Shared<T> {
+        Shared {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T: BusDevice + ?Sized> BusDevice for Shared<T> {
+    fn tick(&mut self) -> Result<(), RError> {
+        self.inner.borrow_mut().tick()
+    }
+
+    fn reset(&mut self) {
+        self.inner.borrow_mut().reset()
+    }
+
+    fn init(&mut self) -> Result<(), RError> {
+        self.inner.borrow_mut().init()
+    }
+
+    fn interrupt(&self) -> bool {
+        self.inner.borrow().interrupt()
+    }
+
+    fn describe(&self) -> String {
+        format!("shared {}", self.inner.borrow().describe())
+    }
+}
+
+impl<T: mem::MemoryBlock + ?Sized> mem::MemoryBlock for Shared<T> {
+    fn get_size(&self) -> usize {
+        self.inner.borrow().get_size()
+    }
+
+    fn set(&mut self, addr: mem::Addr, val: mem::Byte) -> Result<(), Error> {
+        self.inner.borrow_mut().set(addr, val)
+    }
+
+    fn get(&self, addr: mem::Addr) -> Result<mem::Byte, Error> {
+        self.inner.borrow().get(addr)
+    }
+
+    fn delete(&mut self, from: mem::Addr, to: mem::Addr) -> Result<(), Error> {
+        self.inner.borrow_mut().delete(from, to)
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.inner.borrow_mut().flush()
+    }
+}
+
+impl<T: mem::MemoryBlock32be + ?Sized> mem::MemoryBlock32be for Shared<T> {
+    fn set32be(&mut self, addr: mem::Addr, val: u32) -> Result<(), Error> {
+        self.inner.borrow_mut().set32be(addr, val)
+    }
+
+    fn get32be(&self, addr: mem::Addr) -> Result<u32, Error> {
+        self.inner.borrow().get32be(addr)
+    }
+}
+
+impl<T: mem::MemoryBlock32le + ?Sized> mem::MemoryBlock32le for Shared<T> {
+    fn set32le(&mut self, addr: mem::Addr, val: u32) -> Result<(), Error> {
+        self.inner.borrow_mut().set32le(addr, val)
+    }
+
+    fn get32le(&self, addr: mem::Addr) -> Result<u32, Error> {
+        self.inner.borrow().get32le(addr)
+    }
+}
+
+impl<T: MemoryBusDevice + ?Sized> MemoryBusDevice for Shared<T> {}
+impl<T: MemoryBusDevice32be + ?Sized> MemoryBusDevice32be for Shared<T> {
+    fn catch_all(&self) -> bool {
+        self.borrow().catch_all()
+    }
+
+    fn peek32be(&self, addr: mem::Addr) -> Result<u32, Error> {
+        self.borrow().peek32be(addr)
+    }
+}
+impl<T: MemoryBusDevice32le + ?Sized> MemoryBusDevice32le for Shared<T> {}
@@ -0,0 +1,143 @@
+//! Access tracing.
+//!
+//! `Trace` wraps a device and logs every access it takes: the cycle it happened in,
+//! read or write, width, address and value, and the PC with an `Initiator` attached.
+//! Accesses the device turns down, like those of other devices on the same bus,
+//! aren't logged.
+//!
+//! Logs go to stderr, unless told otherwise:
+//!
+//! ```text
+//! Trace: cycle 8, write32 0x80A000C = 0x6C, PC 0x8, SIOTerm at 0x80A000C
+//! ```
+
+extern crate mem;
+
+use self::mem::errors::Error;
+use errors::Error as RError;
+use bus::memorybus::{MemoryBusDevice, MemoryBusDevice32be};
+use bus::{BusDevice, Initiator};
+
+use std::cell::RefCell;
+use std::io::{self, Write};
+
+pub struct Trace {
+    inner: Box<MemoryBusDevice32be>,
+    label: String,
+    out: RefCell<Box<Write>>,
+    initiator: Option<Initiator>,
+    /// Ticks so far.
+    cycle: u64,
+}
+
+impl Trace {
+    pub fn new(inner: Box<MemoryBusDevice32be>) -> Trace {
+        Trace {
+            label: inner.describe(),
+            inner: inner,
+            out: RefCell::new(Box::new(io::stderr())),
+            initiator: None,
+            cycle: 0,
+        }
+    }
+
+    /// Log to `out` instead of stderr.
+    pub fn to(mut self, out: Box<Write>) -> Trace {
+        self.out = RefCell::new(out);
+        self
+    }
+
+    /// Include the PC of the CPU in logs.
+    pub fn with_initiator(mut self, initiator: Initiator) -> Trace {
+        self.initiator = Some(initiator);
+        self
+    }
+
+    fn log(&self, what: &str, addr: mem::Addr, val: u32) {
+        let pc = match self.initiator.as_ref().and_then(|i| i.pc()) {
+            Some(pc) => format!(", PC {:#X}", pc),
+            None => String::new(),
+        };
+        let res = writeln!(self.out.borrow_mut(), "Trace: cycle {}, {} {:#X} = {:#X}{}, {}", self.cycle, what, addr, val, pc, self.label);
+        if let Err(e) = res {
+            debug!("TRACE: failed to log: {}", e);
+        }
+    }
+}
+
+impl mem::MemoryBlock for Trace {
+    fn get_size(&self) -> mem::Addr {
+        self.inner.get_size()
+    }
+
+    fn get(&self, addr: mem::Addr) -> Result<mem::Byte, Error> {
+        let val = self.inner.get(addr)?;
+        self.log("read8", addr, val as u32);
+        Ok(val)
+    }
+
+    fn set(&mut self, addr: mem::Addr, val: mem::Byte) -> Result<(), Error> {
+        self.inner.set(addr, val)?;
+        self.log("write8", addr, val as u32);
+        Ok(())
+    }
+
+    fn delete(&mut self, from: mem::Addr, to: mem::Addr) -> Result<(), Error> {
+        self.inner.delete(from, to)
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.inner.flush()
+    }
+}
+
+impl mem::MemoryBlock32be for Trace {
+    fn get32be(&self, addr: mem::Addr) -> Result<u32, Error> {
+        let val = self.inner.get32be(addr)?;
+        self.log("read32", addr, val);
+        Ok(val)
+    }
+
+    fn set32be(&mut self, addr: mem::Addr, val: u32) -> Result<(), Error> {
+        self.inner.set32be(addr, val)?;
+        self.log("write32", addr, val);
+        Ok(())
+    }
+}
+
+impl BusDevice for Trace {
+    fn describe(&self) -> String {
+        format!("traced {}", self.inner.describe())
+    }
+
+    fn tick(&mut self) -> Result<(), RError> {
+        self.cycle += 1;
+        self.inner.tick()
+    }
+
+    fn init(&mut self) -> Result<(), RError> {
+        self.inner.init()
+    }
+
+    fn reset(&mut self) {
+        self.inner.reset()
+    }
+
+    fn interrupt(&self) -> bool {
+        self.inner.interrupt()
+    }
+}
+impl MemoryBusDevice for Trace {}
+impl MemoryBusDevice32be for Trace {
+    fn devices(&self) -> Vec<&MemoryBusDevice32be> {
+        vec![&*self.inner]
+    }
+
+    fn catch_all(&self) -> bool {
+        self.inner.catch_all()
+    }
+
+    fn peek32be(&self, addr: mem::Addr) -> Result<u32, Error> {
+        self.inner.peek32be(addr)
+    }
+}
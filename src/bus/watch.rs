@@ -0,0 +1,273 @@
+//! Watchpoints.
+//!
+//! `Watch` sits in front of a device, usually a whole bus, and checks every
+//! access going through against a set of `Watchpoint`s. The set is shared:
+//! put layers sharing one in front of the CPU's bus and the DMA controller's,
+//! and both get watched.
+//!
+//! Watchpoints either log the access on stderr, or break: the access still
+//! happens, and a `Machine` handed the set stops once the instruction is done,
+//! with `StopReason::Watchpoint`.
+//!
+//! Instruction fetches the CPU flags through its `Initiator` are not checked,
+//! watchpoints are about data.
+
+extern crate mem;
+
+use self::mem::errors::Error;
+use errors::Error as RError;
+use bus::memorybus::{MemoryBusDevice, MemoryBusDevice32be};
+use bus::{BusDevice, Initiator};
+
+use std::cell::RefCell;
+use std::fmt;
+use std::io;
+use std::io::prelude::*;
+use std::rc::Rc;
+
+/// Accesses a watchpoint fires on.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Access {
+    Read,
+    Write,
+    Any,
+}
+
+/// What a watchpoint does when it fires.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum WatchAction {
+    /// Stop the CPU before its next instruction.
+    Break,
+    /// Tell about the access on stderr.
+    Log,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Watchpoint {
+    /// First address watched.
+    pub from: u32,
+    /// Last address watched.
+    pub to: u32,
+    pub access: Access,
+    /// Only fire when this value gets read or written.
+    pub value: Option<u32>,
+    pub action: WatchAction,
+}
+
+impl Watchpoint {
+    /// Break on writes to the word at `addr`.
+    pub fn new(addr: u32) -> Watchpoint {
+        Watchpoint {
+            from: addr,
+            to: addr.wrapping_add(3),
+            access: Access::Write,
+            value: None,
+            action: WatchAction::Break,
+        }
+    }
+
+    fn matches(&self, addr: u32, width: u32, write: bool, value: u32) -> bool {
+        let last = addr.wrapping_add(width - 1);
+        addr <= self.to && last >= self.from &&
+            match self.access {
+                Access::Read => !write,
+                Access::Write => write,
+                Access::Any => true,
+            } &&
+            self.value.map_or(true, |v| v == value)
+    }
+}
+
+/// An access a watchpoint fired on.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Hit {
+    pub addr: u32,
+    /// Bytes accessed.
+    pub width: u32,
+    pub write: bool,
+    pub value: u32,
+    /// PC of the accessing instruction, if a CPU accessed.
+    pub pc: Option<u32>,
+}
+
+impl fmt::Display for Hit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let what = if self.write { "write" } else { "read" };
+        write!(f, "{}{} of {:#X} at {:#X}", what, self.width * 8, self.value, self.addr)?;
+        match self.pc {
+            Some(pc) => write!(f, ", PC {:#X}", pc),
+            None => write!(f, ", not by the CPU"),
+        }
+    }
+}
+
+#[derive(Default)]
+struct State {
+    points: Vec<Watchpoint>,
+    disabled: bool,
+    /// Hit of a breaking watchpoint, waiting to be taken.
+    pending: Option<Hit>,
+}
+
+/// A set of watchpoints, shared by the layers it's handed to.
+#[derive(Clone, Default)]
+pub struct Watchpoints {
+    state: Rc<RefCell<State>>,
+}
+
+impl Watchpoints {
+    pub fn new() -> Watchpoints {
+        Watchpoints::default()
+    }
+
+    pub fn add(&self, wp: Watchpoint) {
+        self.state.borrow_mut().points.push(wp);
+    }
+
+    /// Remove the watchpoints starting at `addr`, returning whether there were any.
+    pub fn remove(&self, addr: u32) -> bool {
+        let points = &mut self.state.borrow_mut().points;
+        let before = points.len();
+        points.retain(|wp| wp.from != addr);
+        points.len() != before
+    }
+
+    pub fn list(&self) -> Vec<Watchpoint> {
+        self.state.borrow().points.clone()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.state.borrow().points.is_empty()
+    }
+
+    /// Turn checking on or off, for looking at memory without firing them.
+    pub fn set_enabled(&self, enabled: bool) {
+        self.state.borrow_mut().disabled = !enabled;
+    }
+
+    /// Take the hit of a breaking watchpoint since the last time, if any.
+    pub fn take_hit(&self) -> Option<Hit> {
+        self.state.borrow_mut().pending.take()
+    }
+
+    fn check(&self, initiator: &Option<Initiator>, addr: mem::Addr, width: u32, write: bool, value: u32) {
+        let mut state = self.state.borrow_mut();
+        if state.disabled || state.points.is_empty() || initiator.as_ref().map_or(false, |i| i.fetching()) {
+            return;
+        }
+        let addr = addr as u32;
+        let mut hit = None;
+        for wp in state.points.iter().filter(|wp| wp.matches(addr, width, write, value)) {
+            let h = Hit {
+                addr: addr,
+                width: width,
+                write: write,
+                value: value,
+                pc: initiator.as_ref().and_then(|i| i.pc()),
+            };
+            match wp.action {
+                WatchAction::Log => writeln!(io::stderr(), "Watch: {}", h).unwrap(),
+                WatchAction::Break => hit = Some(h),
+            }
+        }
+        // The first one breaks.
+        if state.pending.is_none() {
+            state.pending = hit;
+        }
+    }
+}
+
+pub struct Watch<T: MemoryBusDevice32be> {
+    inner: T,
+    points: Watchpoints,
+    initiator: Option<Initiator>,
+}
+
+impl<T: MemoryBusDevice32be> Watch<T> {
+    pub fn new(inner: T, points: Watchpoints) -> Watch<T> {
+        Watch {
+            inner: inner,
+            points: points,
+            initiator: None,
+        }
+    }
+
+    /// Report the PC of the CPU in hits.
+    pub fn with_initiator(mut self, initiator: Initiator) -> Watch<T> {
+        self.initiator = Some(initiator);
+        self
+    }
+}
+
+impl<T: MemoryBusDevice32be> mem::MemoryBlock for Watch<T> {
+    fn get_size(&self) -> mem::Addr {
+        self.inner.get_size()
+    }
+
+    fn get(&self, addr: mem::Addr) -> Result<mem::Byte, Error> {
+        let val = self.inner.get(addr)?;
+        self.points.check(&self.initiator, addr, 1, false, val as u32);
+        Ok(val)
+    }
+
+    fn set(&mut self, addr: mem::Addr, val: mem::Byte) -> Result<(), Error> {
+        self.inner.set(addr, val)?;
+        self.points.check(&self.initiator, addr, 1, true, val as u32);
+        Ok(())
+    }
+
+    fn delete(&mut self, from: mem::Addr, to: mem::Addr) -> Result<(), Error> {
+        self.inner.delete(from, to)
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.inner.flush()
+    }
+}
+
+impl<T: MemoryBusDevice32be> mem::MemoryBlock32be for Watch<T> {
+    fn get32be(&self, addr: mem::Addr) -> Result<u32, Error> {
+        let val = self.inner.get32be(addr)?;
+        self.points.check(&self.initiator, addr, 4, false, val);
+        Ok(val)
+    }
+
+    fn set32be(&mut self, addr: mem::Addr, val: u32) -> Result<(), Error> {
+        self.inner.set32be(addr, val)?;
+        self.points.check(&self.initiator, addr, 4, true, val);
+        Ok(())
+    }
+}
+
+impl<T: MemoryBusDevice32be> BusDevice for Watch<T> {
+    fn describe(&self) -> String {
+        format!("watchpoints, {} set", self.points.list().len())
+    }
+
+    fn tick(&mut self) -> Result<(), RError> {
+        self.inner.tick()
+    }
+
+    fn init(&mut self) -> Result<(), RError> {
+        self.inner.init()
+    }
+
+    fn reset(&mut self) {
+        self.points.take_hit();
+        self.inner.reset()
+    }
+
+    fn interrupt(&self) -> bool {
+        self.inner.interrupt()
+    }
+}
+impl<T: MemoryBusDevice32be> MemoryBusDevice for Watch<T> {}
+impl<T: MemoryBusDevice32be> MemoryBusDevice32be for Watch<T> {
+    fn devices(&self) -> Vec<&MemoryBusDevice32be> {
+        vec![&self.inner]
+    }
+
+    fn peek32be(&self, addr: mem::Addr) -> Result<u32, Error> {
+        self.inner.peek32be(addr)
+    }
+}
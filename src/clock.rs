@@ -0,0 +1,132 @@
+//! Time keeping for devices.
+//!
+//! Devices which know the time of day get a `TimeSource`, chosen per machine:
+//! either the host's wall clock, or a virtual time derived from the cycle counter,
+//! which keeps reproducible runs reproducible.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Usual start of virtual time, 2000-01-01 00:00:00.
+pub const VIRTUAL_EPOCH: i64 = 946684800;
+/// Usual cycles per virtual second.
+pub const VIRTUAL_HZ: u64 = 1000000;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum TimeSource {
+    /// Host wall clock.
+    Host,
+    /// Starts at `epoch` seconds since the unix epoch, advancing by one second every `hz` cycles.
+    Virtual { epoch: i64, hz: u64 },
+}
+
+/// A clock a device owns. Needs to be ticked every cycle for virtual time.
+#[derive(Debug, Clone)]
+pub struct Clock {
+    source: TimeSource,
+    cycles: u64,
+    /// Seconds set by the guest minus actual time.
+    offset: i64,
+}
+
+impl Clock {
+    pub fn new(source: TimeSource) -> Clock {
+        Clock {
+            source: source,
+            cycles: 0,
+            offset: 0,
+        }
+    }
+
+    #[inline]
+    pub fn tick(&mut self) {
+        self.cycles += 1;
+    }
+
+    pub fn source(&self) -> TimeSource {
+        self.source
+    }
+
+    /// Seconds and nanoseconds since the unix epoch.
+    pub fn now(&self) -> (i64, u32) {
+        let (secs, nanos) = match self.source {
+            TimeSource::Host => match SystemTime::now().duration_since(UNIX_EPOCH) {
+                Ok(d) => (d.as_secs() as i64, d.subsec_nanos()),
+                Err(_) => (0, 0),
+            },
+            TimeSource::Virtual { epoch, hz } => {
+                let hz = if hz == 0 { 1 } else { hz };
+                let secs = epoch.wrapping_add((self.cycles / hz) as i64);
+                let nanos = ((self.cycles % hz) * 1_000_000_000 / hz) as u32;
+                (secs, nanos)
+            },
+        };
+        // Guests can set any time, wrap rather than overflow.
+        (secs.wrapping_add(self.offset), nanos)
+    }
+
+    /// Make the clock read `secs` from now on.
+    pub fn set(&mut self, secs: i64) {
+        self.offset = 0;
+        self.offset = secs.wrapping_sub(self.now().0);
+    }
+}
+
+/// Broken down date and time.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct DateTime {
+    pub year: i64,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+    /// 0 is sunday.
+    pub weekday: u8,
+}
+
+impl DateTime {
+    pub fn from_unix(t: i64) -> DateTime {
+        let days = t.div_euclid(86400);
+        let secs = t.rem_euclid(86400);
+        let (y, m, d) = civil_from_days(days);
+        DateTime {
+            year: y,
+            month: m as u8,
+            day: d as u8,
+            hour: (secs / 3600) as u8,
+            minute: (secs / 60 % 60) as u8,
+            second: (secs % 60) as u8,
+            // 1970-01-01 was a thursday.
+            weekday: (days + 4).rem_euclid(7) as u8,
+        }
+    }
+
+    pub fn to_unix(&self) -> i64 {
+        days_from_civil(self.year, self.month as i64, self.day as i64) * 86400 +
+            self.hour as i64 * 3600 + self.minute as i64 * 60 + self.second as i64
+    }
+}
+
+// Calendar conversion, from http://howardhinnant.github.io/date_algorithms.html
+
+fn days_from_civil(y: i64, m: i64, d: i64) -> i64 {
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + d - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+fn civil_from_days(z: i64) -> (i64, i64, i64) {
+    let z = z + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    let y = yoe + era * 400 + if m <= 2 { 1 } else { 0 };
+    (y, m, d)
+}
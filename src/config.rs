@@ -0,0 +1,570 @@
+//! Machine descriptions.
+//!
+//! Declares a machine in TOML: the CPU and its initial registers, the devices
+//! on its bus in the order they get asked, their parameters and the images to load.
+//! `Config::build` assembles it, so new boards don't need any code.
+//!
+//! Numbers may be integers or strings, as TOML has no hex literals: `"0x80000"`.
+//! Relative paths are relative to the description file.
+//!
+//! ```toml
+//! [cpu]
+//! type = "zpu"
+//! variant = "full"  # hardware EMULATEs, "small" leaves them to software
+//! pc = 0
+//! sp = "0x80000"
+//! # mmu = "0x080b0000"  # MMU with its registers there
+//!
+//! [clock]
+//! source = "virtual"  # or "host", the default
+//! # epoch = 946684800
+//! # hz = 1000000
+//!
+//! [[device]]
+//! type = "phi"
+//!
+//! [[device]]
+//! type = "ram"
+//! base = 0
+//! size = "0x80000"
+//! image = "hello.bin"
+//! # file = "ram.img"  # keep RAM in this file across runs
+//!
+//! [[device]]
+//! type = "unmapped"
+//! policy = "log"
+//! ```
+//!
+//! Any device takes `trace = true`, logging its accesses on stderr, see `bus::trace`.
+//!
+//! Device types and their parameters, besides `base`:
+//!
+//! - `ram`: `size`, optional `image` and `file`.
+//! - `rom`: `image`, optional `policy`: `ignore`, `fault` or `log`.
+//! - `sio`, `gpio`, `rtc`, `exit`.
+//! - `phi`, without base: I/O page of the Phi platform, optional `mhz`.
+//! - `zpuino`, without base: I/O slots of ZPUino, optional `flash` with the SPI flash contents.
+//! - `watchdog`: optional `timeout` in cycles and `action`: `reset` or `halt`.
+//! - `rng`: optional `seed`, host entropy without one.
+//! - `dma`: optional `channels`, 1 to 16, with access to all RAM declared before it.
+//! - `framebuffer`: `width`, `height`, `bpp` (1, 8, 16 or 32) and `cycles_per_frame`.
+//!   Optional `dump_dir` to save frames into, every `dump_every` frames, as `dump_format`: `ppm` or `png`.
+//! - `eth`: optional `mac`, like `"02:00:00:00:00:01"`, and `network`: `unplugged`, `loopback`,
+//!   `pcap` reading `input` and writing `output`, both optional, or `udp` from `bind` to `peer`.
+//!   Rings and buffers are in the RAM declared before it.
+//! - `audio`: `output` WAV file, optional `rate` in Hz and `cpu_hz`, the CPU clock.
+//! - `i2c`: I2C master, with slaves in `[[device.slave]]` tables: `type` `eeprom` with `size`
+//!   and optional `file`, or `ds1307`. Either takes an optional `addr`.
+//! - `mapper`: `image` in bank switched ROM, `cartridge`: `uxrom` or `mbc1`.
+//! - `unmapped`, without base: optional `policy`: `openbus`, `fault` or `log`, and `open_bus`.
+
+extern crate toml;
+extern crate serde;
+
+use self::serde::de::{self, Deserialize, Deserializer, Visitor};
+
+use errors::*;
+use bus::Initiator;
+use bus::shared::Shared;
+use bus::mmu::MMU;
+use bus::watch::{Watch, Watchpoints};
+use bus::trace::Trace;
+use bus::memorybus::{MemoryBus32be, MemoryBusDevice32be};
+use clock::{self, TimeSource};
+use cpu::CPU;
+use num::parse_num;
+use cpu::zpu::ZPU;
+use devices::memorybus::sio::SIOTerm;
+use devices::memorybus::gpio::GPIO;
+use devices::memorybus::rtc::RTC;
+use devices::memorybus::watchdog::{Watchdog, WatchdogAction};
+use devices::memorybus::rng::RNG;
+use devices::memorybus::dma::DMA;
+use devices::memorybus::framebuffer::{Framebuffer, ImageFormat};
+use devices::memorybus::eth::EthMAC;
+use devices::memorybus::audio::Audio;
+use devices::memorybus::i2c::I2CMaster;
+use devices::memorybus::rom::{ROM, WritePolicy};
+use devices::memorybus::unmapped::{Unmapped, UnmappedPolicy};
+use devices::memorybus::mapper::Mapper;
+use devices::memorybus::mmap::{MemMap, MapMode};
+use devices::memorybus::exit::Exit;
+use platforms::{phi, zpuino};
+use devices::serial::{SerialBackend, Stdio, Terminal};
+use devices::net::{EthBackend, Loopback, Unplugged};
+use devices::net::pcap::Pcap;
+use devices::net::udp::Udp;
+use devices::i2c::{eeprom, ds1307};
+use devices::i2c::eeprom::EEPROM24;
+use devices::i2c::ds1307::DS1307;
+use bus::i2c::I2CBus;
+
+use std::io::prelude::*;
+use std::fs::{self, File};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// A number, given as integer or string.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Num(pub u64);
+
+impl Num {
+    fn addr(&self) -> usize {
+        self.0 as usize
+    }
+}
+
+struct NumVisitor;
+
+impl<'de> Visitor<'de> for NumVisitor {
+    type Value = Num;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a number or a string containing one")
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> ::std::result::Result<Num, E> {
+        if v < 0 {
+            return Err(E::custom(format!("negative number: {}", v)));
+        }
+        Ok(Num(v as u64))
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> ::std::result::Result<Num, E> {
+        Ok(Num(v))
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> ::std::result::Result<Num, E> {
+        parse_num(v).map(Num).map_err(E::custom)
+    }
+}
+
+impl<'de> Deserialize<'de> for Num {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> ::std::result::Result<Num, D::Error> {
+        d.deserialize_any(NumVisitor)
+    }
+}
+
+#[derive(Debug, Deserialize, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum CPUType {
+    ZPU,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Variant {
+    /// Hardware EMULATE implementations.
+    Full,
+    /// EMULATEs are left to software.
+    Small,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct CPUConfig {
+    #[serde(rename = "type")]
+    pub kind: CPUType,
+    pub variant: Option<Variant>,
+    pub pc: Option<Num>,
+    pub sp: Option<Num>,
+    /// Address of the MMU registers, if there is one.
+    pub mmu: Option<Num>,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Source {
+    Host,
+    Virtual,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct ClockConfig {
+    pub source: Source,
+    pub epoch: Option<i64>,
+    pub hz: Option<Num>,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Policy {
+    Ignore,
+    Fault,
+    Log,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Unmapping {
+    OpenBus,
+    Fault,
+    Log,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Reset,
+    Halt,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    PPM,
+    PNG,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Network {
+    Unplugged,
+    Loopback,
+    Pcap,
+    Udp,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Cartridge {
+    UxROM,
+    MBC1,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum SlaveConfig {
+    Eeprom { addr: Option<Num>, size: Num, file: Option<String> },
+    DS1307 { addr: Option<Num> },
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum DeviceConfig {
+    Ram { base: Num, size: Num, image: Option<String>, file: Option<String> },
+    Rom { base: Num, image: String, policy: Option<Policy> },
+    Sio { base: Num },
+    Gpio { base: Num },
+    Rtc { base: Num },
+    Exit { base: Num },
+    Phi { mhz: Option<Num> },
+    ZPUino { flash: Option<String> },
+    Watchdog { base: Num, timeout: Option<Num>, action: Option<Action> },
+    Rng { base: Num, seed: Option<Num> },
+    Dma { base: Num, channels: Option<Num> },
+    Framebuffer {
+        base: Num, width: Num, height: Num, bpp: Num, cycles_per_frame: Num,
+        dump_dir: Option<String>, dump_every: Option<Num>, dump_format: Option<Format>,
+    },
+    Eth {
+        base: Num, mac: Option<String>, network: Option<Network>,
+        input: Option<String>, output: Option<String>, bind: Option<String>, peer: Option<String>,
+    },
+    Audio { base: Num, output: String, rate: Option<Num>, cpu_hz: Option<Num> },
+    I2c { base: Num, #[serde(default, rename = "slave")] slaves: Vec<SlaveConfig> },
+    Mapper { base: Num, image: String, cartridge: Cartridge },
+    Unmapped { policy: Option<Unmapping>, open_bus: Option<Num> },
+}
+
+/// A device, with the parameters every type takes.
+#[derive(Debug, Deserialize, Clone)]
+pub struct Device {
+    #[serde(flatten)]
+    pub kind: DeviceConfig,
+    /// Log its accesses.
+    #[serde(default)]
+    pub trace: bool,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Config {
+    pub cpu: CPUConfig,
+    pub clock: Option<ClockConfig>,
+    #[serde(default, rename = "device")]
+    pub devices: Vec<Device>,
+
+    /// Relative paths are relative to this.
+    #[serde(skip)]
+    pub dir: PathBuf,
+}
+
+/// Load the description at `path`.
+pub fn load<P: AsRef<Path>>(path: P) -> Result<Config, Error> {
+    let path = path.as_ref();
+    let mut s = String::new();
+    File::open(path)?.read_to_string(&mut s)?;
+    let mut config = parse(&s).chain_err(|| format!("invalid machine description: {}", path.display()))?;
+    config.dir = path.parent().map_or(PathBuf::new(), |p| p.to_path_buf());
+    Ok(config)
+}
+
+/// Parse a description, relative paths are relative to the working directory.
+pub fn parse(s: &str) -> Result<Config, Error> {
+    toml::from_str(s).chain_err(|| "failed to parse machine description")
+}
+
+impl Config {
+    fn path(&self, p: &str) -> PathBuf {
+        self.dir.join(p)
+    }
+
+    fn time(&self) -> TimeSource {
+        match self.clock {
+            Some(ClockConfig { source: Source::Virtual, epoch, hz }) => TimeSource::Virtual {
+                epoch: epoch.unwrap_or(clock::VIRTUAL_EPOCH),
+                hz: hz.map_or(clock::VIRTUAL_HZ, |n| n.0),
+            },
+            _ => TimeSource::Host,
+        }
+    }
+
+    /// Memory of a `ram` device, mapped at 0.
+    fn ram(&self, size: usize, image: &Option<String>, file: &Option<String>) -> Result<MemMap, Error> {
+        Ok(match (image, file) {
+            (_, &Some(ref file)) => {
+                let mut ram = MemMap::open(self.path(file), size, MapMode::Persistent)?;
+                if let Some(ref image) = *image {
+                    let path = self.path(image);
+                    let mut data = vec![];
+                    File::open(&path)?.read_to_end(&mut data)?;
+                    if data.len() > size {
+                        bail!("{} doesn't fit into {:#X} bytes", path.display(), size);
+                    }
+                    ram.as_mut_slice()[..data.len()].copy_from_slice(&data);
+                }
+                ram
+            },
+            (&Some(ref image), &None) => MemMap::open(self.path(image), size, MapMode::CopyOnWrite)?,
+            (&None, &None) => MemMap::anon(size)?,
+        })
+    }
+
+    /// Assemble the machine, ready to be started.
+    pub fn build(&self) -> Result<Box<CPU>, Error> {
+        self.build_with(Box::new(Terminal::new()), Watchpoints::new())
+    }
+
+    /// Assemble the machine, with `console` on the first UART instead of the terminal,
+    /// and `watch` checking the accesses of CPU and DMA.
+    pub fn build_with(&self, console: Box<SerialBackend>, watch: Watchpoints) -> Result<Box<CPU>, Error> {
+        let mut console = Some(console);
+        let time = self.time();
+        let initiator = Initiator::new();
+        let mut rams: Vec<(usize, Shared<MemMap>)> = vec![];
+        let mut devices: Vec<Box<MemoryBusDevice32be>> = vec![];
+
+        for device in &self.devices {
+            let dev: Box<MemoryBusDevice32be> = match device.kind {
+                DeviceConfig::Ram { base, size, ref image, ref file } => {
+                    let ram = Shared::new(self.ram(size.addr(), image, file)?);
+                    rams.push((base.addr(), ram.clone()));
+                    at(base.addr(), ram)
+                },
+                DeviceConfig::Rom { base, ref image, policy } => {
+                    let path = self.path(image);
+                    let len = fs::metadata(&path)?.len() as usize;
+                    if len == 0 {
+                        bail!("ROM image {} is empty", path.display());
+                    }
+                    // Whole words, so the last one can be fetched.
+                    let mem = MemMap::open(&path, (len + 3) & !3, MapMode::CopyOnWrite)?;
+                    let policy = match policy.unwrap_or(Policy::Ignore) {
+                        Policy::Ignore => WritePolicy::Ignore,
+                        Policy::Fault => WritePolicy::Fault,
+                        Policy::Log => WritePolicy::Log,
+                    };
+                    Box::new(ROM::new(base.addr(), mem, policy))
+                },
+                DeviceConfig::Sio { base } => Box::new(SIOTerm::new_zpu(base.addr()).with_serial(take(&mut console))),
+                DeviceConfig::Gpio { base } => Box::new(GPIO::new(base.addr())),
+                DeviceConfig::Rtc { base } => Box::new(RTC::new(base.addr(), time)),
+                DeviceConfig::Exit { base } => Box::new(Exit::new(base.addr())),
+                DeviceConfig::Phi { mhz } => {
+                    let io = phi::io(mhz.map_or(1, |n| n.0 as u32), take(&mut console));
+                    devices.extend(io.into_iter().map(|dev| traced(dev, device.trace, &initiator)));
+                    continue;
+                },
+                DeviceConfig::ZPUino { ref flash } => {
+                    let mut data = vec![];
+                    if let Some(ref flash) = *flash {
+                        File::open(self.path(flash))?.read_to_end(&mut data)?;
+                    }
+                    let io = zpuino::io(data, take(&mut console));
+                    devices.extend(io.into_iter().map(|dev| traced(dev, device.trace, &initiator)));
+                    continue;
+                },
+                DeviceConfig::Watchdog { base, timeout, action } => {
+                    let action = match action.unwrap_or(Action::Reset) {
+                        Action::Reset => WatchdogAction::Reset,
+                        Action::Halt => WatchdogAction::Halt,
+                    };
+                    Box::new(Watchdog::new(base.addr(), timeout.map_or(1000000, |n| n.0 as u32), action))
+                },
+                DeviceConfig::Rng { base, seed } => match seed {
+                    Some(seed) => Box::new(RNG::new_seeded(base.addr(), seed.0)),
+                    None => Box::new(RNG::new_host(base.addr())?),
+                },
+                DeviceConfig::Dma { base, channels } => {
+                    let channels = channels.map_or(4, |n| n.addr());
+                    if channels < 1 || channels > 16 {
+                        bail!("dma: channels must be 1 to 16, not {}", channels);
+                    }
+                    let bus = MemoryBus32be::new(rams.iter().map(|&(at_base, ref ram)| at(at_base, ram.clone())).collect());
+                    Box::new(DMA::new(base.addr(), channels, Box::new(Watch::new(bus, watch.clone()))))
+                },
+                DeviceConfig::Framebuffer {
+                    base, width, height, bpp, cycles_per_frame, ref dump_dir, dump_every, dump_format,
+                } => {
+                    match bpp.addr() {
+                        1 | 8 | 16 | 32 => (),
+                        bpp => bail!("framebuffer: bpp must be 1, 8, 16 or 32, not {}", bpp),
+                    }
+                    if width.addr() == 0 || height.addr() == 0 {
+                        bail!("framebuffer: width and height must not be 0");
+                    }
+                    let mut fb = Framebuffer::new(base.addr(), width.addr(), height.addr(), bpp.addr(), cycles_per_frame.0);
+                    if let Some(ref dir) = *dump_dir {
+                        let format = match dump_format.unwrap_or(Format::PPM) {
+                            Format::PPM => ImageFormat::PPM,
+                            Format::PNG => ImageFormat::PNG,
+                        };
+                        fb.dump_every(dump_every.map_or(1, |n| n.0 as u32), self.path(dir), format);
+                    }
+                    Box::new(fb)
+                },
+                DeviceConfig::Eth { base, ref mac, network, ref input, ref output, ref bind, ref peer } => {
+                    let mac = match *mac {
+                        Some(ref mac) => parse_mac(mac)?,
+                        None => [0x02, 0, 0, 0, 0, 0x01],
+                    };
+                    let backend: Box<EthBackend> = match network.unwrap_or(Network::Unplugged) {
+                        Network::Unplugged => Box::new(Unplugged),
+                        Network::Loopback => Box::new(Loopback::new()),
+                        Network::Pcap => Box::new(Pcap::new(input.as_ref().map(|p| self.path(p)), output.as_ref().map(|p| self.path(p)))?),
+                        Network::Udp => {
+                            let (bind, peer) = match (bind, peer) {
+                                (&Some(ref bind), &Some(ref peer)) => (bind, peer),
+                                _ => bail!("eth: udp needs bind and peer"),
+                            };
+                            let bind = bind.parse().chain_err(|| format!("eth: invalid bind address: {}", bind))?;
+                            let peer = peer.parse().chain_err(|| format!("eth: invalid peer address: {}", peer))?;
+                            Box::new(Udp::new(bind, peer)?)
+                        },
+                    };
+                    let bus = MemoryBus32be::new(rams.iter().map(|&(at_base, ref ram)| at(at_base, ram.clone())).collect());
+                    Box::new(EthMAC::new(base.addr(), mac, Box::new(Watch::new(bus, watch.clone())), backend))
+                },
+                DeviceConfig::Audio { base, ref output, rate, cpu_hz } => {
+                    let rate = rate.map_or(44100, |n| n.0 as u32);
+                    let cpu_hz = cpu_hz.map_or(clock::VIRTUAL_HZ, |n| n.0);
+                    Box::new(Audio::new(base.addr(), self.path(output), rate, cpu_hz)?)
+                },
+                DeviceConfig::I2c { base, ref slaves } => {
+                    let mut bus = I2CBus::new();
+                    for slave in slaves {
+                        match *slave {
+                            SlaveConfig::Eeprom { addr, size, ref file } => {
+                                let size = size.addr();
+                                if !size.is_power_of_two() || size < 128 || size > 0x10000 {
+                                    bail!("i2c: eeprom size must be a power of two from 128 to 65536, not {}", size);
+                                }
+                                let eeprom = match *file {
+                                    Some(ref file) => EEPROM24::open(self.path(file), size)?,
+                                    None => EEPROM24::new(size),
+                                };
+                                bus.attach(slave_addr(addr, eeprom::ADDR)?, Box::new(eeprom));
+                            },
+                            SlaveConfig::DS1307 { addr } => {
+                                bus.attach(slave_addr(addr, ds1307::ADDR)?, Box::new(DS1307::new(time)));
+                            },
+                        }
+                    }
+                    Box::new(I2CMaster::new(base.addr(), bus))
+                },
+                DeviceConfig::Mapper { base, ref image, cartridge } => {
+                    let path = self.path(image);
+                    let len = fs::metadata(&path)?.len() as usize;
+                    if len == 0 {
+                        bail!("mapper image {} is empty", path.display());
+                    }
+                    let mem = MemMap::open(&path, (len + 3) & !3, MapMode::CopyOnWrite)?;
+                    match cartridge {
+                        Cartridge::UxROM => Box::new(Mapper::uxrom(base.addr(), mem)),
+                        Cartridge::MBC1 => Box::new(Mapper::mbc1(base.addr(), mem)),
+                    }
+                },
+                DeviceConfig::Unmapped { policy, open_bus } => {
+                    let open_bus = open_bus.map_or(0, |n| n.0 as u8);
+                    let policy = match policy.unwrap_or(Unmapping::Fault) {
+                        Unmapping::OpenBus => UnmappedPolicy::OpenBus(open_bus),
+                        Unmapping::Fault => UnmappedPolicy::Fault,
+                        Unmapping::Log => UnmappedPolicy::Log(open_bus),
+                    };
+                    Box::new(Unmapped::new(policy).with_initiator(initiator.clone()))
+                },
+            };
+            devices.push(traced(dev, device.trace, &initiator));
+        }
+
+        let bus = Watch::new(MemoryBus32be::new(devices), watch).with_initiator(initiator.clone());
+        let mut bus: Box<MemoryBusDevice32be> = match self.cpu.mmu {
+            Some(addr) => Box::new(MMU::new(bus, addr.addr()).with_initiator(initiator.clone())),
+            None => Box::new(bus),
+        };
+        bus.init()?;
+
+        match self.cpu.kind {
+            CPUType::ZPU => {
+                let mut cpu = ZPU::new(bus, self.cpu.variant.unwrap_or(Variant::Full) == Variant::Full);
+                cpu.pc = self.cpu.pc.map_or(0, |n| n.0 as u32);
+                cpu.sp = self.cpu.sp.map_or(0, |n| n.0 as u32);
+                cpu.set_initiator(initiator);
+                Ok(Box::new(cpu))
+            },
+        }
+    }
+}
+
+/// The console, if it wasn't taken yet, or the terminal.
+fn take(console: &mut Option<Box<SerialBackend>>) -> Box<SerialBackend> {
+    console.take().unwrap_or_else(|| Box::new(Stdio))
+}
+
+/// `dev`, logging its accesses if `trace` is set.
+fn traced(dev: Box<MemoryBusDevice32be>, trace: bool, initiator: &Initiator) -> Box<MemoryBusDevice32be> {
+    if !trace {
+        return dev;
+    }
+    Box::new(Trace::new(dev).with_initiator(initiator.clone()))
+}
+
+/// 7 bit I2C address `addr`, or `default`.
+fn slave_addr(addr: Option<Num>, default: u8) -> Result<u8, Error> {
+    match addr {
+        Some(Num(addr)) if addr > 0x7F => bail!("i2c: slave address must be 7 bits, not {:#X}", addr),
+        Some(Num(addr)) => Ok(addr as u8),
+        None => Ok(default),
+    }
+}
+
+/// MAC address written like `02:00:00:00:00:01`.
+fn parse_mac(s: &str) -> Result<[u8; 6], Error> {
+    let mut mac = [0; 6];
+    let parts: Vec<&str> = s.split(':').collect();
+    if parts.len() != 6 {
+        bail!("eth: invalid MAC address: {}", s);
+    }
+    for (byte, part) in mac.iter_mut().zip(parts) {
+        *byte = u8::from_str_radix(part, 16).chain_err(|| format!("eth: invalid MAC address: {}", s))?;
+    }
+    Ok(mac)
+}
+
+/// Memory mapped at 0, moved to `base`.
+fn at<T: MemoryBusDevice32be + 'static>(base: usize, mem: T) -> Box<MemoryBusDevice32be> {
+    if base == 0 {
+        return Box::new(mem);
+    }
+    let size = mem.get_size();
+    Box::new(Mapper::new(mem, true).fixed_window(base, size, 0))
+}
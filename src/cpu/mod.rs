@@ -6,6 +6,8 @@ extern crate byteorder;
 use cpu::byteorder::{ByteOrder, BigEndian};
 
 use errors::*;
+use bus::Initiator;
+use bus::memorybus::MemoryBusDevice32be;
 
 // Handy aliases
 type Byte = u8;
@@ -27,8 +29,29 @@ pub trait CPU {
     /// Get state.
     fn state(&self) -> CPUState;
 
+    /// Program counter.
+    fn pc(&self) -> u32;
+
+    /// Stack pointer.
+    fn sp(&self) -> u32;
+
+    /// The bus, for looking at memory and devices.
+    fn bus(&self) -> &MemoryBusDevice32be;
+
+    fn bus_mut(&mut self) -> &mut MemoryBusDevice32be;
+
+    /// Disassemble the instruction at `addr`, returning it and its length.
+    fn disassemble(&self, addr: u32) -> Result<(String, u32), Error>;
+
     /// Start.
     fn start(&mut self) -> Result<(), Error>;
+
+    /// Reset the CPU and the devices on its bus.
+    /// Registers get the values they had when the CPU was first started.
+    fn reset(&mut self) -> Result<(), Error>;
+
+    /// Report bus accesses to `initiator`.
+    fn set_initiator(&mut self, initiator: Initiator);
 }
 
 // Helpers
@@ -8,19 +8,30 @@ extern crate mem;
 
 use errors::*;
 use super::CPUState as State;
+use bus::memorybus::MemoryBusDevice32be;
+use bus::Initiator;
 
 // Handy aliases
 type Byte = u8;
 
+/// Where interrupts go.
+const INTERRUPT_VECTOR: u32 = 0x20;
+
 pub struct ZPU {
     pub sp: u32,
     pub pc: u32,
     last_im: bool,
+    /// SP after entering the interrupt being handled.
+    /// Interrupts stay masked until POPINT, or the POPPC returning from it.
+    interrupt_sp: Option<u32>,
 
     state: State,
+    /// PC and SP at first start, restored on reset.
+    reset_to: Option<(u32, u32)>,
 
-    pub mem: Box<mem::MemoryBlock32be>,
+    pub mem: Box<MemoryBusDevice32be>,
     hwemus: bool,
+    initiator: Option<Initiator>,
 }
 
 // Helpers
@@ -130,37 +141,36 @@ impl ZPU {
         self.sp = self.sp.wrapping_add(4) & 0xFFFFFFFC;
         Ok(v)
     }
-}
-
-impl ZPU {
-    pub fn new(mem: Box<mem::MemoryBlock32be>, emus: bool) -> ZPU {
-        ZPU {
-            pc: 0,
-            sp: 0,
-            last_im: false,
-
-            state: State::Stopped,
 
-            mem: mem,
-            hwemus: emus,
-        }
+    /// Did a device fail the last access as a trap for the guest, instead of a fatal fault?
+    fn take_trap(&self) -> bool {
+        self.initiator.as_ref().map_or(false, |i| i.take_trap())
     }
-}
-
-impl super::CPU for ZPU {
-    /// Run one instruction.
-    fn step(&mut self) -> Result<(), Error> { // TODO: make it use a custom error type or something.
-        // Bail out if not running
-        if self.state != State::Running {
-            bail!(ErrorKind::CPUNotRunning);
-        }
 
+    /// Fetch and run an instruction.
+    fn execute(&mut self) -> Result<(), Error> {
         // Debug
         debug!("");
         debugf!("{} ({:x}/{:x}) :", self.pc, self.sp, match self.get32(self.sp) { Ok(val) => val, Err(_) => 0});
 
         // Get op
-        let op = self.mem.get((self.pc) as usize).chain_err(|| "ZPU failed to fetch OP")?;
+        if let Some(ref i) = self.initiator {
+            i.set_fetching(true);
+        }
+        let op = self.mem.get((self.pc) as usize);
+        if let Some(ref i) = self.initiator {
+            i.set_fetching(false);
+        }
+        let op = match op {
+            Ok(op) => op,
+            Err(e) => {
+                // Taking a trap here could split an IM sequence.
+                if self.last_im {
+                    self.take_trap();
+                }
+                return Err(e).chain_err(|| "ZPU failed to fetch OP");
+            },
+        };
         let lim = self.last_im;
         self.last_im = false;
 
@@ -181,9 +191,17 @@ impl super::CPU for ZPU {
                 self.pc = self.pc.wrapping_add(1);
                 true
             },
-            // 0x03 = POPINT?
+            0x03 => { // POPINT
+                debug!("POPINT");
+                self.pc = self.v_pop()?;
+                self.interrupt_sp = None;
+                true
+            },
             0x04 => { // POPPC
                 debug!("POPPC");
+                if self.interrupt_sp == Some(sp) {
+                    self.interrupt_sp = None;
+                }
                 self.pc = self.v_pop()?;
                 true
             },
@@ -337,6 +355,76 @@ impl super::CPU for ZPU {
         bail!("ZPU OP not implemented: {:#X}", op)
     }
 
+}
+
+impl ZPU {
+    pub fn new(mem: Box<MemoryBusDevice32be>, emus: bool) -> ZPU {
+        ZPU {
+            pc: 0,
+            sp: 0,
+            last_im: false,
+            interrupt_sp: None,
+
+            state: State::Stopped,
+            reset_to: None,
+
+            mem: mem,
+            hwemus: emus,
+            initiator: None,
+        }
+    }
+
+    /// Byte at `addr`, peeked so looking at code doesn't disturb devices.
+    fn peek(&self, addr: u32) -> Result<Byte, Error> {
+        let word = self.mem.peek32be((addr & !3) as usize)?;
+        Ok((word >> ((3 - (addr & 3)) * 8)) as Byte)
+    }
+}
+
+impl super::CPU for ZPU {
+    /// Run one instruction.
+    fn step(&mut self) -> Result<(), Error> { // TODO: make it use a custom error type or something.
+        // Bail out if not running
+        if self.state != State::Running {
+            bail!(ErrorKind::CPUNotRunning);
+        }
+
+        // Clock the devices
+        if let Some(ref i) = self.initiator {
+            i.set_pc(None);
+        }
+        self.mem.tick()?;
+        if let Some(ref i) = self.initiator {
+            i.set_pc(Some(self.pc));
+        }
+
+        // Take interrupts, but don't split IM sequences.
+        if self.interrupt_sp.is_none() && !self.last_im && self.mem.interrupt() {
+            debug!("ZPU: interrupt at {:#X}", self.pc);
+            let pc = self.pc;
+            self.v_push(pc)?;
+            self.interrupt_sp = Some(self.sp);
+            self.pc = INTERRUPT_VECTOR;
+            return Ok(());
+        }
+
+        // Run the instruction. One abandoned for a trap runs again once it's handled.
+        let (pc, sp, interrupt_sp) = (self.pc, self.sp, self.interrupt_sp);
+        self.take_trap();
+        let res = self.execute();
+        // Traps are taken like interrupts, so not while one is handled.
+        if res.is_err() && self.take_trap() && interrupt_sp.is_none() {
+            debug!("ZPU: trap at {:#X}", pc);
+            self.pc = pc;
+            self.sp = sp;
+            self.interrupt_sp = interrupt_sp;
+            // Only instructions that got fetched trap after an IM, and those don't continue it.
+            self.last_im = false;
+            return Ok(());
+        }
+        res
+    }
+
     // State stuff
 
     // Get state
@@ -344,11 +432,123 @@ impl super::CPU for ZPU {
         self.state.clone()
     }
 
+    fn pc(&self) -> u32 {
+        self.pc
+    }
+
+    fn sp(&self) -> u32 {
+        self.sp
+    }
+
+    fn bus(&self) -> &MemoryBusDevice32be {
+        &*self.mem
+    }
+
+    fn bus_mut(&mut self) -> &mut MemoryBusDevice32be {
+        &mut *self.mem
+    }
+
+    /// IM sequences are shown as one instruction, with the value they push.
+    fn disassemble(&self, addr: u32) -> Result<(String, u32), Error> {
+        let op = self.peek(addr)?;
+        if (op & 0x80) == 0 {
+            return Ok((disassemble(op), 1));
+        }
+        // Sign extended like the first IM does.
+        let mut val = if (op & 0x40) != 0 { (op as u32) | 0xFFFFFF80 } else { (op & 0x7F) as u32 };
+        let mut len = 1;
+        while let Ok(op) = self.peek(addr.wrapping_add(len)) {
+            if (op & 0x80) == 0 {
+                break;
+            }
+            val = val.wrapping_shl(7) | (op & 0x7F) as u32;
+            len += 1;
+        }
+        Ok((format!("IM {:#X}", val), len))
+    }
+
     // Start
     fn start(&mut self) -> Result<(), Error> {
+        if self.reset_to.is_none() {
+            self.reset_to = Some((self.pc, self.sp));
+        }
+        self.state = State::Running;
+        Ok(())
+    }
+
+    // Reset
+    fn reset(&mut self) -> Result<(), Error> {
+        let (pc, sp) = self.reset_to.unwrap_or((0, 0));
+        self.pc = pc;
+        self.sp = sp;
+        self.last_im = false;
+        self.interrupt_sp = None;
+        self.mem.reset();
         self.state = State::Running;
         Ok(())
     }
+
+    fn set_initiator(&mut self, initiator: Initiator) {
+        self.initiator = Some(initiator);
+    }
+}
+
+/// Mnemonic of a single op, IMs with their 7 bits.
+pub fn disassemble(op: Byte) -> String {
+    let name = match op {
+        0x00 => "BREAKPOINT",
+        0x02 => "PUSHSP",
+        0x03 => "POPINT",
+        0x04 => "POPPC",
+        0x05 => "ADD",
+        0x06 => "AND",
+        0x07 => "OR",
+        0x08 => "LOAD",
+        0x09 => "NOT",
+        0x0A => "FLIP",
+        0x0B => "NOP",
+        0x0C => "STORE",
+        0x0D => "POPSP",
+        op if (op & 0x80) == 0x80 => return format!("IM {:#X}", op & 0x7F),
+        op if (op & 0xE0) == 0x40 => return format!("STORESP {}", (((op ^ 0x10) & 0x1F) as u32) << 2),
+        op if (op & 0xE0) == 0x60 => return format!("LOADSP {}", (((op ^ 0x10) & 0x1F) as u32) << 2),
+        op if (op & 0xF0) == 0x10 => return format!("ADDSP {}", ((op & 0x0F) as u32) << 2),
+        op if (op & 0xE0) == 0x20 => match op & 0x1F {
+            2 => "LOADH",
+            3 => "STOREH",
+            4 => "LESSTHAN",
+            5 => "LESSTHANEQUAL",
+            6 => "ULESSTHAN",
+            7 => "ULESSTHANEQUAL",
+            8 => "SWAP",
+            9 => "SLOWMULT",
+            10 => "LSHIFTRIGHT",
+            11 => "ASHIFTLEFT",
+            12 => "ASHIFTRIGHT",
+            13 => "CALL",
+            14 => "EQ",
+            15 => "NEQ",
+            16 => "NEG",
+            17 => "SUB",
+            18 => "XOR",
+            19 => "LOADB",
+            20 => "STOREB",
+            21 => "DIV",
+            22 => "MOD",
+            23 => "EQBRANCH",
+            24 => "NEQBRANCH",
+            25 => "POPPCREL",
+            26 => "CONFIG",
+            27 => "PUSHPC",
+            28 => "SYSCALL",
+            29 => "PUSHSPADD",
+            30 => "HALFMULT",
+            31 => "CALLPCREL",
+            eop => return format!("EMULATE {}", eop),
+        },
+        op => return format!("INVALID {:#X}", op),
+    };
+    name.to_string()
 }
 
 /// Emulates.
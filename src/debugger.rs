@@ -0,0 +1,316 @@
+//! Full screen debugger.
+//!
+//! Draws the machine with ANSI escapes: disassembly around the PC, the stack,
+//! breakpoints and watchpoints, a memory view, the serial console and the
+//! output of the last commands, with a command line at the bottom.
+//! Commands are those of the monitor, plus:
+//!
+//! - `mem ADDR`: show memory from ADDR.
+//! - `send TEXT`: queue TEXT as console input, with the escapes of expect scripts.
+//! - `c`, `continue`: run until stopped, or until enter is pressed.
+//!
+//! Only uses the `CPU` trait and the bus, so it works with any core.
+//! Memory is peeked like the monitor does, so redrawing doesn't disturb devices.
+
+extern crate libc;
+
+use errors::*;
+use machine::{Machine, StopReason};
+use monitor::{self, Monitor, Action};
+use expect;
+use devices::serial::Buffer;
+use bus::watch::Watchpoints;
+use num::parse_num;
+
+use std::cmp;
+use std::collections::VecDeque;
+use std::io::{self, BufRead, Write};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Rows of the code, stack and breakpoint panes.
+const TOP_ROWS: usize = 8;
+/// Rows of the memory pane, 16 bytes each.
+const MEM_ROWS: usize = 4;
+/// Rows of command output shown.
+const LOG_ROWS: usize = 3;
+/// Command output kept.
+const LOG_KEEP: usize = 100;
+/// Instructions shown before the PC, at most.
+const CODE_BEFORE: u32 = 3;
+/// Cycles run between looking for input while continuing.
+const CHUNK: u64 = 10000;
+/// How often the screen is redrawn while continuing.
+const REDRAW: u64 = 100;
+
+pub struct Debugger {
+    monitor: Monitor,
+    console: Buffer,
+    log: VecDeque<String>,
+    /// Start of the memory view.
+    mem_addr: u32,
+    cols: usize,
+    rows: usize,
+}
+
+impl Debugger {
+    /// Debug a machine with `console` on its UART.
+    pub fn new(console: Buffer) -> Debugger {
+        Debugger {
+            monitor: Monitor::new(),
+            console: console,
+            log: VecDeque::new(),
+            mem_addr: 0,
+            cols: 80,
+            rows: 24,
+        }
+    }
+
+    /// Size of the terminal, 80x24 by default. See `terminal_size`.
+    pub fn with_size(mut self, cols: usize, rows: usize) -> Debugger {
+        self.cols = cmp::max(cols, 40);
+        self.rows = cmp::max(rows, 1 + (TOP_ROWS + 1) + (MEM_ROWS + 1) + 2 + LOG_ROWS + 1);
+        self
+    }
+
+    /// Manage the watchpoints of a `Watch` layer on the machine's bus.
+    pub fn with_watchpoints(mut self, watch: Watchpoints) -> Debugger {
+        self.monitor = self.monitor.with_watchpoints(watch);
+        self
+    }
+
+    pub fn monitor_mut(&mut self) -> &mut Monitor {
+        &mut self.monitor
+    }
+
+    /// Debug until quit, reading commands from stdin.
+    pub fn run(&mut self, machine: &mut Machine, out: &mut Write) -> Result<(), Error> {
+        // Lines are read on their own thread, to notice them while the machine runs.
+        let (tx, lines) = mpsc::channel();
+        thread::spawn(move || {
+            let stdin = io::stdin();
+            for line in stdin.lock().lines() {
+                match line {
+                    Ok(line) => if tx.send(line).is_err() { break },
+                    Err(_) => break,
+                }
+            }
+        });
+
+        loop {
+            self.draw(machine, out)?;
+            let line = match lines.recv() {
+                Ok(line) => line,
+                Err(_) => break,
+            };
+            match self.command(machine, &line, &lines, out) {
+                Ok(Action::Quit) => break,
+                Ok(_) => (),
+                Err(e) => self.print(&format!("error: {}", e)),
+            }
+        }
+        write!(out, "\x1b[2J\x1b[H")?;
+        out.flush()?;
+        Ok(())
+    }
+
+    fn command(&mut self, machine: &mut Machine, line: &str, lines: &Receiver<String>, out: &mut Write) -> Result<Action, Error> {
+        let trimmed = line.trim();
+        let (cmd, arg) = match trimmed.find(char::is_whitespace) {
+            Some(at) => (&trimmed[..at], trimmed[at..].trim_start()),
+            None => (trimmed, ""),
+        };
+        self.print(&format!("> {}", trimmed));
+        match cmd {
+            "mem" => {
+                self.mem_addr = parse_num(arg)?;
+                Ok(Action::Stay)
+            },
+            "send" => {
+                self.console.send(&expect::unescape(arg)?);
+                Ok(Action::Stay)
+            },
+            "c" | "continue" => {
+                self.cont(machine, lines, out)?;
+                Ok(Action::Stay)
+            },
+            _ => {
+                let mut buf = vec![];
+                let res = self.monitor.command(machine, line, &mut buf);
+                for l in String::from_utf8_lossy(&buf).lines() {
+                    self.print(l);
+                }
+                res
+            },
+        }
+    }
+
+    /// Run until stopped, or a line is entered.
+    fn cont(&mut self, machine: &mut Machine, lines: &Receiver<String>, out: &mut Write) -> Result<(), Error> {
+        machine.resume();
+        let mut drawn = Instant::now();
+        loop {
+            match self.monitor.run_for(machine, CHUNK) {
+                StopReason::Limit => (),
+                reason => {
+                    self.print(&monitor::describe_stop(&reason));
+                    return Ok(());
+                },
+            }
+            match lines.try_recv() {
+                Ok(_) => {
+                    self.print("interrupted");
+                    return Ok(());
+                },
+                Err(TryRecvError::Disconnected) => return Ok(()),
+                Err(TryRecvError::Empty) => (),
+            }
+            if drawn.elapsed() >= Duration::from_millis(REDRAW) {
+                self.draw(machine, out)?;
+                drawn = Instant::now();
+            }
+        }
+    }
+
+    /// Add a line to the command output.
+    fn print(&mut self, line: &str) {
+        self.log.push_back(line.to_string());
+        while self.log.len() > LOG_KEEP {
+            self.log.pop_front();
+        }
+    }
+
+    /// Draw the whole screen, leaving the cursor on the command line.
+    pub fn draw(&self, machine: &Machine, out: &mut Write) -> Result<(), Error> {
+        let mut screen = String::from("\x1b[H");
+        for (i, line) in self.frame(machine).iter().enumerate() {
+            if i > 0 {
+                screen.push_str("\r\n");
+            }
+            screen.push_str(line);
+            screen.push_str("\x1b[K");
+        }
+        screen.push_str("\x1b[J");
+        write!(out, "{}", screen)?;
+        out.flush()?;
+        Ok(())
+    }
+
+    /// The lines of the screen.
+    fn frame(&self, machine: &Machine) -> Vec<String> {
+        let cpu = machine.cpu();
+        let pc = cpu.pc();
+        let mut lines = vec![];
+        lines.push(title(&format!("PC {:#010X}  SP {:#010X}  cycles {}", pc, cpu.sp(), machine.cycles()), self.cols));
+
+        // Code, stack and breakpoints side by side.
+        let code_w = self.cols / 2;
+        let stack_w = (self.cols - code_w) / 2;
+        let list_w = self.cols - code_w - stack_w;
+        let code = self.code(machine);
+        let stack: Vec<String> = (0..TOP_ROWS as u32).map(|i| {
+            let addr = cpu.sp().wrapping_add(i * 4);
+            let val = monitor::word(cpu, addr);
+            format!(" {:08X}: {}", addr, val)
+        }).collect();
+        let mut list: Vec<String> = vec![];
+        let mut bps: Vec<&u32> = machine.breakpoints().iter().collect();
+        bps.sort();
+        list.extend(bps.iter().map(|pc| format!(" break {:08X}", pc)));
+        list.extend(self.monitor.watchpoints().iter().map(|wp| format!(" watch {:08X}", wp.from)));
+        lines.push(format!("{}{}{}", title("Code", code_w), title("Stack", stack_w), title("Break/Watch", list_w)));
+        for i in 0..TOP_ROWS {
+            let get = |v: &Vec<String>| v.get(i).cloned().unwrap_or_default();
+            lines.push(format!("{}{}{}", fit(&get(&code), code_w), fit(&get(&stack), stack_w), fit(&get(&list), list_w)));
+        }
+
+        lines.push(title(&format!("Memory {:#010X}", self.mem_addr), self.cols));
+        for line in monitor::hexdump(cpu, self.mem_addr, (MEM_ROWS * 16) as u32) {
+            lines.push(fit(&line, self.cols));
+        }
+
+        let console_rows = self.rows - lines.len() - 1 - LOG_ROWS - 1 - 1;
+        lines.push(title("Console", self.cols));
+        let output = String::from_utf8_lossy(&self.console.output()).into_owned();
+        let console: Vec<&str> = output.split('\n').collect();
+        let skip = console.len().saturating_sub(console_rows);
+        for i in 0..console_rows {
+            let line = console.get(skip + i).map_or(String::new(), |l| printable(l));
+            lines.push(fit(&line, self.cols));
+        }
+
+        lines.push(title("Output", self.cols));
+        let skip = self.log.len().saturating_sub(LOG_ROWS);
+        for i in 0..LOG_ROWS {
+            lines.push(fit(self.log.get(skip + i).map_or("", |l| &l[..]), self.cols));
+        }
+        lines.push("(dbg) ".to_string());
+        lines
+    }
+
+    /// Disassembly from a few instructions before the PC on.
+    fn code(&self, machine: &Machine) -> Vec<String> {
+        let cpu = machine.cpu();
+        let pc = cpu.pc();
+        // Instructions before the PC that end right at it.
+        let mut before = vec![];
+        let mut at = pc.saturating_sub(CODE_BEFORE * 4);
+        while at < pc {
+            let len = cpu.disassemble(at).map_or(1, |(_, len)| len);
+            if at.saturating_add(len) > pc {
+                before.clear();
+            } else {
+                before.push(at);
+            }
+            at += len;
+        }
+        let skip = before.len().saturating_sub(CODE_BEFORE as usize);
+        let mut res = vec![];
+        for &at in &before[skip..] {
+            res.extend(monitor::disassemble(cpu, at, 1));
+        }
+        res.extend(monitor::disassemble(cpu, pc, TOP_ROWS as u32 - res.len() as u32));
+        res.iter().map(|&(at, ref text)| {
+            let mark = if at == pc { '>' } else if machine.breakpoints().contains(&at) { '*' } else { ' ' };
+            format!("{}{:08X}: {}", mark, at, text)
+        }).collect()
+    }
+}
+
+/// Pad or cut `s` to `width` columns.
+fn fit(s: &str, width: usize) -> String {
+    let mut s: String = s.chars().take(width).collect();
+    let len = s.chars().count();
+    s.extend(::std::iter::repeat(' ').take(width - len));
+    s
+}
+
+/// A pane title, in reverse video.
+fn title(s: &str, width: usize) -> String {
+    format!("\x1b[7m{}\x1b[0m", fit(&format!(" {}", s), width))
+}
+
+/// Guest output without control characters, which would mess up the screen.
+fn printable(s: &str) -> String {
+    s.trim_end_matches('\r').chars().map(|c| match c {
+        '\t' => ' ',
+        c if c.is_control() => '.',
+        c => c,
+    }).collect()
+}
+
+/// Columns and rows of the terminal on stdout, if it is one.
+#[cfg(unix)]
+pub fn terminal_size() -> Option<(usize, usize)> {
+    let mut ws: libc::winsize = unsafe { ::std::mem::zeroed() };
+    if unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut ws) } != 0 || ws.ws_col == 0 || ws.ws_row == 0 {
+        return None;
+    }
+    Some((ws.ws_col as usize, ws.ws_row as usize))
+}
+
+#[cfg(not(unix))]
+pub fn terminal_size() -> Option<(usize, usize)> {
+    None
+}
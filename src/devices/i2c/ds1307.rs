@@ -0,0 +1,152 @@
+//! DS1307 style real time clock.
+//!
+//! Keeps time from a `TimeSource`, set to whatever the guest
+//! writes to the time registers.
+//! Setting the clock halt bit, bit 7 of seconds, stops time until it is cleared.
+//! Registers `0x00` to `0x06` hold the time in BCD, `0x07` is control
+//! and `0x08` to `0x3F` are battery backed RAM.
+
+use bus::i2c::I2CDevice;
+use clock::{Clock, DateTime, TimeSource};
+
+/// Default address of the DS1307.
+pub const ADDR: u8 = 0x68;
+
+const REG_SECONDS: usize = 0;
+const REG_HOURS: usize = 2;
+const TIME_REGS: usize = 7;
+const CLOCK_HALT: u8 = 0x80;
+
+pub struct DS1307 {
+    regs: [u8; 64],
+    ptr: usize,
+    first: bool,
+    clock: Clock,
+    time_written: bool,
+    /// Time it was halted at.
+    stopped: Option<i64>,
+}
+
+impl DS1307 {
+    pub fn new(source: TimeSource) -> DS1307 {
+        DS1307 {
+            regs: [0; 64],
+            ptr: 0,
+            first: false,
+            clock: Clock::new(source),
+            time_written: false,
+            stopped: None,
+        }
+    }
+
+    /// Guest time, as seconds since the unix epoch.
+    pub fn now(&self) -> i64 {
+        match self.stopped {
+            Some(t) => t,
+            None => self.clock.now().0,
+        }
+    }
+
+    fn halted(&self) -> bool {
+        self.regs[REG_SECONDS] & CLOCK_HALT != 0
+    }
+
+    /// Copy the current time into the time registers.
+    fn latch(&mut self) {
+        if self.halted() {
+            return;
+        }
+        let dt = DateTime::from_unix(self.now());
+        let twelve = self.regs[REG_HOURS] & 0x40 != 0;
+
+        self.regs[0] = bcd(dt.second);
+        self.regs[1] = bcd(dt.minute);
+        self.regs[2] = if twelve {
+            let pm = if dt.hour >= 12 { 0x20 } else { 0 };
+            let h12 = match dt.hour % 12 { 0 => 12, h => h };
+            0x40 | pm | bcd(h12)
+        } else {
+            bcd(dt.hour)
+        };
+        // Day 1 is sunday.
+        self.regs[3] = dt.weekday + 1;
+        self.regs[4] = bcd(dt.day);
+        self.regs[5] = bcd(dt.month);
+        self.regs[6] = bcd((dt.year % 100) as u8);
+    }
+
+    /// Take the time registers as the new guest time.
+    /// While halted it stays there, once running again the clock goes on from it.
+    fn apply(&mut self) {
+        let hour = if self.regs[2] & 0x40 != 0 {
+            let h = unbcd(self.regs[2] & 0x1F) % 12;
+            if self.regs[2] & 0x20 != 0 { h + 12 } else { h }
+        } else {
+            unbcd(self.regs[2] & 0x3F)
+        };
+        let dt = DateTime {
+            year: 2000 + unbcd(self.regs[6]) as i64,
+            month: unbcd(self.regs[5] & 0x1F),
+            day: unbcd(self.regs[4] & 0x3F),
+            hour: hour,
+            minute: unbcd(self.regs[1] & 0x7F),
+            second: unbcd(self.regs[0] & 0x7F),
+            weekday: 0,
+        };
+        if self.halted() {
+            self.stopped = Some(dt.to_unix());
+        } else {
+            self.stopped = None;
+            self.clock.set(dt.to_unix());
+        }
+    }
+}
+
+impl I2CDevice for DS1307 {
+    fn start(&mut self, _addr: u8, read: bool) -> bool {
+        self.latch();
+        self.first = !read;
+        true
+    }
+
+    fn write(&mut self, val: u8) -> bool {
+        if self.first {
+            self.ptr = (val & 0x3F) as usize;
+            self.first = false;
+            return true;
+        }
+        if self.ptr < TIME_REGS {
+            self.time_written = true;
+        }
+        self.regs[self.ptr] = val;
+        self.ptr = (self.ptr + 1) & 0x3F;
+        true
+    }
+
+    fn read(&mut self) -> u8 {
+        let val = self.regs[self.ptr];
+        self.ptr = (self.ptr + 1) & 0x3F;
+        val
+    }
+
+    fn stop(&mut self) {
+        if self.time_written {
+            self.apply();
+            self.time_written = false;
+        }
+    }
+
+    fn tick(&mut self) {
+        self.clock.tick()
+    }
+}
+
+#[inline]
+fn bcd(v: u8) -> u8 {
+    ((v / 10) << 4) | (v % 10)
+}
+
+#[inline]
+fn unbcd(v: u8) -> u8 {
+    (v >> 4) * 10 + (v & 0xF)
+}
@@ -0,0 +1,142 @@
+//! 24Cxx serial EEPROM.
+//!
+//! 24C01 up to 24C16 take one address byte, with the bigger ones
+//! using the low device address bits to select a 256 byte block.
+//! 24C32 and up take two address bytes.
+//!
+//! Optionally backed by a file, which gets updated after every write cycle.
+
+use bus::i2c::I2CDevice;
+use errors::*;
+
+use std::io;
+use std::io::prelude::*;
+use std::fs::{File, OpenOptions};
+use std::path::{Path, PathBuf};
+
+/// Usual address of 24Cxx EEPROMs.
+pub const ADDR: u8 = 0x50;
+
+pub struct EEPROM24 {
+    data: Vec<u8>,
+    file: Option<PathBuf>,
+
+    page: usize,
+    addr_bytes: usize,
+
+    /// Address pointer.
+    ptr: usize,
+    /// Address bytes received in the current write transaction.
+    addr_recv: usize,
+    dirty: bool,
+}
+
+impl EEPROM24 {
+    /// New, erased EEPROM of `size` bytes. Contents are lost at exit.
+    pub fn new(size: usize) -> EEPROM24 {
+        assert!(size.is_power_of_two() && size >= 128 && size <= 0x10000, "unsupported EEPROM size");
+        let page = if size <= 256 {
+            8
+        } else if size <= 2048 {
+            16
+        } else if size <= 8192 {
+            32
+        } else if size <= 32768 {
+            64
+        } else {
+            128
+        };
+        EEPROM24 {
+            data: vec![0xFF; size],
+            file: None,
+
+            page: page,
+            addr_bytes: if size > 2048 { 2 } else { 1 },
+
+            ptr: 0,
+            addr_recv: 0,
+            dirty: false,
+        }
+    }
+
+    /// EEPROM of `size` bytes backed by `path`.
+    /// The file gets created if it does not exist.
+    pub fn open<P: AsRef<Path>>(path: P, size: usize) -> Result<EEPROM24, Error> {
+        let mut eeprom = EEPROM24::new(size);
+        let mut f = OpenOptions::new().read(true).write(true).create(true).open(path.as_ref())?;
+        let mut buf = vec![];
+        f.read_to_end(&mut buf)?;
+        let len = if buf.len() < size { buf.len() } else { size };
+        eeprom.data[..len].copy_from_slice(&buf[..len]);
+        eeprom.file = Some(path.as_ref().to_path_buf());
+        Ok(eeprom)
+    }
+
+    /// Contents.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Write the contents back to the backing file, if any.
+    pub fn save(&mut self) -> Result<(), Error> {
+        if let Some(ref path) = self.file {
+            let mut f = File::create(path)?;
+            f.write_all(&self.data)?;
+        }
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+impl I2CDevice for EEPROM24 {
+    fn start(&mut self, addr: u8, read: bool) -> bool {
+        if self.addr_bytes == 1 {
+            // Block select bits.
+            let block = (addr as usize) & (self.span() as usize - 1);
+            self.ptr = (block << 8) | (self.ptr & 0xFF);
+        }
+        if !read {
+            self.addr_recv = 0;
+        }
+        true
+    }
+
+    fn write(&mut self, val: u8) -> bool {
+        if self.addr_recv < self.addr_bytes {
+            self.ptr = if self.addr_bytes == 2 && self.addr_recv == 0 {
+                (val as usize) << 8
+            } else {
+                (self.ptr & !0xFF) | val as usize
+            };
+            self.ptr %= self.data.len();
+            self.addr_recv += 1;
+            return true;
+        }
+        self.data[self.ptr] = val;
+        self.dirty = true;
+        // Page writes wrap around within the page.
+        self.ptr = (self.ptr & !(self.page - 1)) | ((self.ptr + 1) & (self.page - 1));
+        true
+    }
+
+    fn read(&mut self) -> u8 {
+        let val = self.data[self.ptr];
+        self.ptr = (self.ptr + 1) % self.data.len();
+        val
+    }
+
+    fn stop(&mut self) {
+        if self.dirty {
+            if let Err(e) = self.save() {
+                writeln!(io::stderr(), "EEPROM: failed to save contents: {}", e).unwrap();
+            }
+        }
+    }
+
+    fn span(&self) -> u8 {
+        if self.addr_bytes == 1 && self.data.len() > 256 {
+            return (self.data.len() / 256) as u8;
+        }
+        1
+    }
+}
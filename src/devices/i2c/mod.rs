@@ -0,0 +1,3 @@
+// I2C slaves.
+pub mod eeprom;
+pub mod ds1307;
@@ -0,0 +1,322 @@
+//! PCM audio output, rendered to a WAV file.
+//!
+//! The guest pushes signed 16 bit samples into a FIFO, which gets drained
+//! at the sample rate, timed against the emulated clock.
+//! A running dry FIFO sets the underrun bit and plays silence.
+//! On top of that, three square wave tone channels, PSG style.
+//!
+//! The WAV file is 16 bit mono at a fixed rate, the PCM stream is resampled to it.
+//! It stops growing at the 4 GiB a WAV file can hold.
+//!
+//! Registers, 32 bit big endian, relative to `base`:
+//!
+//! - `0x00` CTRL: bit 0 enables PCM playback.
+//! - `0x04` STATUS: `STATUS_*` bits. Write 1 to clear underrun.
+//! - `0x08` RATE: PCM sample rate in Hz.
+//! - `0x0C` FIFO: write only, pushes a sample. Byte writes push signed 8 bit samples.
+//! - `0x10` LEVEL: read only, samples in the FIFO.
+//! - `0x14` IRQ_MASK: `STATUS_UNDERRUN` and `STATUS_LOW` raise the interrupt.
+//! - `0x20 + 8 * n` TONE_FREQ, `0x24 + 8 * n` TONE_VOL: frequency in Hz and
+//!   volume from 0 to 15 of tone channel `n`, 0 to 2.
+
+extern crate mem;
+
+use self::mem::errors::*;
+use bus::memorybus::{MemoryBusDevice, MemoryBusDevice32be};
+use bus::BusDevice;
+use errors::Error as RError;
+
+use super::{Registers, reg_set_byte};
+
+use std::io;
+use std::io::prelude::*;
+use std::io::{BufWriter, SeekFrom};
+use std::fs::File;
+use std::path::Path;
+use std::collections::VecDeque;
+
+const REG_CTRL: mem::Addr = 0x00;
+const REG_STATUS: mem::Addr = 0x04;
+const REG_RATE: mem::Addr = 0x08;
+const REG_FIFO: mem::Addr = 0x0C;
+const REG_LEVEL: mem::Addr = 0x10;
+const REG_IRQ_MASK: mem::Addr = 0x14;
+const REG_TONE: mem::Addr = 0x20;
+const TONES: usize = 3;
+const SIZE: mem::Addr = REG_TONE + TONES * 8;
+
+const CTRL_ENABLE: u32 = 0x01;
+
+pub const STATUS_UNDERRUN: u32 = 0x01;
+pub const STATUS_FULL: u32 = 0x02;
+pub const STATUS_EMPTY: u32 = 0x04;
+/// FIFO is less than half full.
+pub const STATUS_LOW: u32 = 0x08;
+
+const FIFO_SIZE: usize = 1024;
+
+/// Most sample bytes a WAV file holds, its sizes are 32 bit.
+const WAV_MAX_DATA: u32 = (0xFFFFFFFF - 36) & !1;
+
+#[derive(Default, Clone, Copy)]
+struct Tone {
+    freq: u32,
+    vol: u32,
+    phase: u32,
+}
+
+pub struct Audio {
+    base: mem::Addr,
+    out: BufWriter<File>,
+    out_rate: u32,
+    written: u32,
+    cpu_hz: u64,
+
+    ctrl: u32,
+    underrun: bool,
+    rate: u32,
+    irq_mask: u32,
+    fifo: VecDeque<i16>,
+    tones: [Tone; TONES],
+
+    /// Sample being played.
+    current: i16,
+    /// Fractional cycle accumulators for PCM and output samples.
+    pcm_acc: u64,
+    out_acc: u64,
+}
+
+impl Audio {
+    /// Render to the WAV file `path` at `out_rate` Hz. The CPU runs at `cpu_hz` cycles per second.
+    pub fn new<P: AsRef<Path>>(base: mem::Addr, path: P, out_rate: u32, cpu_hz: u64) -> Result<Audio, RError> {
+        if out_rate == 0 {
+            bail!("audio output rate must not be 0");
+        }
+        if out_rate.checked_mul(2).is_none() {
+            bail!("audio output rate {} is too high for a WAV file", out_rate);
+        }
+        let mut out = BufWriter::new(File::create(path)?);
+        write_wav_header(&mut out, out_rate, 0)?;
+        Ok(Audio {
+            base: base,
+            out: out,
+            out_rate: out_rate,
+            written: 0,
+            cpu_hz: cpu_hz,
+
+            ctrl: 0,
+            underrun: false,
+            rate: 8000,
+            irq_mask: 0,
+            fifo: VecDeque::with_capacity(FIFO_SIZE),
+            tones: [Tone::default(); TONES],
+
+            current: 0,
+            pcm_acc: 0,
+            out_acc: 0,
+        })
+    }
+
+    /// Fix up the WAV header, so the file is valid up to here.
+    pub fn finish(&mut self) -> io::Result<()> {
+        self.out.flush()?;
+        let f = self.out.get_mut();
+        f.seek(SeekFrom::Start(0))?;
+        write_wav_header(f, self.out_rate, self.written)?;
+        f.seek(SeekFrom::End(0))?;
+        Ok(())
+    }
+
+    fn status(&self) -> u32 {
+        let mut status = 0;
+        if self.underrun {
+            status |= STATUS_UNDERRUN;
+        }
+        if self.fifo.len() >= FIFO_SIZE {
+            status |= STATUS_FULL;
+        }
+        if self.fifo.is_empty() {
+            status |= STATUS_EMPTY;
+        }
+        if self.fifo.len() < FIFO_SIZE / 2 {
+            status |= STATUS_LOW;
+        }
+        status
+    }
+
+    fn push(&mut self, sample: i16) {
+        if self.fifo.len() < FIFO_SIZE {
+            self.fifo.push_back(sample);
+        }
+    }
+
+    /// Next PCM sample is due.
+    fn next_pcm(&mut self) {
+        if self.ctrl & CTRL_ENABLE == 0 {
+            self.current = 0;
+            return;
+        }
+        match self.fifo.pop_front() {
+            Some(sample) => self.current = sample,
+            None => {
+                if !self.underrun {
+                    debug!("AUDIO: underrun");
+                }
+                self.underrun = true;
+                self.current = 0;
+            },
+        }
+    }
+
+    /// Mix and write one output sample.
+    fn render(&mut self) -> io::Result<()> {
+        let mut mix = self.current as i32;
+        for tone in self.tones.iter_mut() {
+            if tone.freq == 0 || tone.vol == 0 {
+                continue;
+            }
+            tone.phase = tone.phase.wrapping_add(((tone.freq as u64) << 32).wrapping_div(self.out_rate as u64) as u32);
+            let amp = (tone.vol as i32 & 0xF) * 0x2000 / 15;
+            mix += if tone.phase < 0x80000000 { amp } else { -amp };
+        }
+        let sample = if mix > 0x7FFF { 0x7FFF } else if mix < -0x8000 { -0x8000 } else { mix } as i16;
+        let written = match self.written.checked_add(2) {
+            Some(written) if written <= WAV_MAX_DATA => written,
+            // The WAV file is full, drop the rest.
+            _ => return Ok(()),
+        };
+        self.out.write_all(&[sample as u8, (sample >> 8) as u8])?;
+        self.written = written;
+        if written == WAV_MAX_DATA {
+            writeln!(io::stderr(), "AUDIO: WAV file is full, not writing any more samples").unwrap();
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Audio {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
+
+fn write_wav_header<W: Write>(w: &mut W, rate: u32, data_len: u32) -> io::Result<()> {
+    let mut hdr = Vec::with_capacity(44);
+    hdr.extend_from_slice(b"RIFF");
+    put_u32_le(&mut hdr, data_len.saturating_add(36));
+    hdr.extend_from_slice(b"WAVEfmt ");
+    put_u32_le(&mut hdr, 16);
+    hdr.extend_from_slice(&[1, 0, 1, 0]); // PCM, mono
+    put_u32_le(&mut hdr, rate);
+    put_u32_le(&mut hdr, rate.saturating_mul(2)); // byte rate
+    hdr.extend_from_slice(&[2, 0, 16, 0]); // block align, bits per sample
+    hdr.extend_from_slice(b"data");
+    put_u32_le(&mut hdr, data_len);
+    w.write_all(&hdr)
+}
+
+fn put_u32_le(out: &mut Vec<u8>, v: u32) {
+    out.extend_from_slice(&[v as u8, (v >> 8) as u8, (v >> 16) as u8, (v >> 24) as u8]);
+}
+
+impl Registers for Audio {
+    fn base(&self) -> mem::Addr {
+        self.base
+    }
+
+    fn size(&self) -> mem::Addr {
+        SIZE
+    }
+
+    fn read_reg(&self, reg: mem::Addr) -> u32 {
+        match reg {
+            REG_CTRL => self.ctrl,
+            REG_STATUS => self.status(),
+            REG_RATE => self.rate,
+            REG_LEVEL => self.fifo.len() as u32,
+            REG_IRQ_MASK => self.irq_mask,
+            _ if reg >= REG_TONE && reg < SIZE => {
+                let tone = &self.tones[(reg - REG_TONE) / 8];
+                if reg & 4 == 0 { tone.freq } else { tone.vol }
+            },
+            _ => 0,
+        }
+    }
+
+    fn write_reg(&mut self, reg: mem::Addr, val: u32) {
+        match reg {
+            REG_CTRL => self.ctrl = val,
+            REG_STATUS => if val & STATUS_UNDERRUN != 0 { self.underrun = false },
+            REG_RATE => self.rate = val,
+            REG_FIFO => self.push(val as i16),
+            REG_IRQ_MASK => self.irq_mask = val,
+            _ if reg >= REG_TONE && reg < SIZE => {
+                let tone = &mut self.tones[(reg - REG_TONE) / 8];
+                if reg & 4 == 0 {
+                    tone.freq = val;
+                } else {
+                    tone.vol = val & 0xF;
+                }
+            },
+            _ => (),
+        }
+    }
+
+    fn write_reg_byte(&mut self, reg: mem::Addr, addr: mem::Addr, val: mem::Byte) {
+        match reg {
+            REG_FIFO => {
+                if addr & 3 == 3 {
+                    self.push((val as i8 as i16) << 8);
+                }
+            },
+            REG_STATUS => self.write_reg(reg, reg_set_byte(0, addr, val)),
+            _ => {
+                let new = reg_set_byte(self.read_reg(reg), addr, val);
+                self.write_reg(reg, new);
+            },
+        }
+    }
+}
+registers!(Audio, {
+    fn flush(&mut self) -> Result<(), Error> {
+        if self.finish().is_err() {
+            bail!(ErrorKind::HardwareFault(self.base, "Audio device failed to write WAV file."));
+        }
+        Ok(())
+    }
+});
+
+impl BusDevice for Audio {
+    fn describe(&self) -> String {
+        format!("audio at {:#X}", self.base)
+    }
+
+    fn tick(&mut self) -> Result<(), RError> {
+        self.pcm_acc += self.rate as u64;
+        if self.pcm_acc >= self.cpu_hz {
+            self.pcm_acc -= self.cpu_hz;
+            self.next_pcm();
+        }
+        self.out_acc += self.out_rate as u64;
+        if self.out_acc >= self.cpu_hz {
+            self.out_acc -= self.cpu_hz;
+            self.render()?;
+        }
+        Ok(())
+    }
+
+    fn reset(&mut self) {
+        self.ctrl = 0;
+        self.underrun = false;
+        self.irq_mask = 0;
+        self.fifo.clear();
+        self.tones = [Tone::default(); TONES];
+        self.current = 0;
+    }
+
+    fn interrupt(&self) -> bool {
+        self.status() & self.irq_mask & (STATUS_UNDERRUN | STATUS_LOW) != 0
+    }
+}
+impl MemoryBusDevice for Audio {}
+impl MemoryBusDevice32be for Audio {}
@@ -0,0 +1,218 @@
+//! DMA controller.
+//!
+//! Copies memory around on its own, over a bus of its own.
+//! That bus is usually a `MemoryBus32be` of `Shared` handles to the
+//! devices also on the CPU's bus, minus the controller itself.
+//!
+//! Transfers steal cycles: each busy channel moves one unit per cycle,
+//! interleaved with the CPU.
+//!
+//! Registers, 32 bit big endian, relative to `base`:
+//!
+//! - `0x00` STATUS: bit `n` is set when channel `n` finished,
+//!   bit `16 + n` when it stopped on a bus error. Write 1 to clear.
+//! - `0x10 + 0x10 * n` SRC, `0x14 + 0x10 * n` DST: addresses of channel `n`.
+//! - `0x18 + 0x10 * n` LEN: units left to transfer.
+//! - `0x1C + 0x10 * n` CTRL: `CTRL_*` bits and the `MODE_*` of source and destination,
+//!   shifted by `CTRL_SRC_SHIFT` and `CTRL_DST_SHIFT`.
+
+extern crate mem;
+
+use self::mem::errors::*;
+use bus::memorybus::{MemoryBusDevice, MemoryBusDevice32be};
+use bus::BusDevice;
+use errors::Error as RError;
+
+use super::{Registers, reg_set_byte};
+
+const REG_STATUS: mem::Addr = 0x00;
+const REG_CHANNELS: mem::Addr = 0x10;
+const CH_SRC: mem::Addr = 0x0;
+const CH_DST: mem::Addr = 0x4;
+const CH_LEN: mem::Addr = 0x8;
+const CH_CTRL: mem::Addr = 0xC;
+
+/// Channel is busy. Set to start, cleared when done.
+pub const CTRL_ENABLE: u32 = 0x01;
+/// Move words instead of bytes.
+pub const CTRL_WORD: u32 = 0x02;
+/// Raise the interrupt when done.
+pub const CTRL_IRQ: u32 = 0x04;
+pub const CTRL_SRC_SHIFT: u32 = 4;
+pub const CTRL_DST_SHIFT: u32 = 6;
+
+/// Address stays, for FIFO registers.
+pub const MODE_FIXED: u32 = 0;
+pub const MODE_INC: u32 = 1;
+pub const MODE_DEC: u32 = 2;
+
+#[derive(Default, Clone, Copy)]
+struct Channel {
+    src: u32,
+    dst: u32,
+    len: u32,
+    ctrl: u32,
+}
+
+impl Channel {
+    fn busy(&self) -> bool {
+        self.ctrl & CTRL_ENABLE != 0
+    }
+}
+
+#[inline]
+fn advance(addr: u32, mode: u32, step: u32) -> u32 {
+    match mode & 3 {
+        MODE_INC => addr.wrapping_add(step),
+        MODE_DEC => addr.wrapping_sub(step),
+        _ => addr,
+    }
+}
+
+pub struct DMA {
+    base: mem::Addr,
+    bus: Box<mem::MemoryBlock32be>,
+
+    channels: Vec<Channel>,
+    status: u32,
+    /// Channel to serve next, round robin.
+    next: usize,
+}
+
+impl DMA {
+    /// DMA controller with `channels` channels, up to 16.
+    pub fn new(base: mem::Addr, channels: usize, bus: Box<mem::MemoryBlock32be>) -> DMA {
+        assert!(channels > 0 && channels <= 16, "DMA supports 1 to 16 channels");
+        DMA {
+            base: base,
+            bus: bus,
+
+            channels: vec![Channel::default(); channels],
+            status: 0,
+            next: 0,
+        }
+    }
+
+    /// Move one unit on channel `n`.
+    fn transfer(&mut self, n: usize) -> Result<(), Error> {
+        let ch = self.channels[n];
+        let step = if ch.ctrl & CTRL_WORD != 0 {
+            let val = self.bus.get32be(ch.src as mem::Addr)?;
+            self.bus.set32be(ch.dst as mem::Addr, val)?;
+            4
+        } else {
+            let val = self.bus.get(ch.src as mem::Addr)?;
+            self.bus.set(ch.dst as mem::Addr, val)?;
+            1
+        };
+        let ch = &mut self.channels[n];
+        ch.src = advance(ch.src, ch.ctrl >> CTRL_SRC_SHIFT, step);
+        ch.dst = advance(ch.dst, ch.ctrl >> CTRL_DST_SHIFT, step);
+        ch.len -= 1;
+        Ok(())
+    }
+}
+
+impl Registers for DMA {
+    fn base(&self) -> mem::Addr {
+        self.base
+    }
+
+    fn size(&self) -> mem::Addr {
+        REG_CHANNELS + self.channels.len() * 0x10
+    }
+
+    fn read_reg(&self, reg: mem::Addr) -> u32 {
+        if reg == REG_STATUS {
+            return self.status;
+        }
+        if reg < REG_CHANNELS {
+            return 0;
+        }
+        let ch = &self.channels[(reg - REG_CHANNELS) / 0x10];
+        match reg & 0xF {
+            CH_SRC => ch.src,
+            CH_DST => ch.dst,
+            CH_LEN => ch.len,
+            CH_CTRL => ch.ctrl,
+            _ => 0,
+        }
+    }
+
+    fn write_reg(&mut self, reg: mem::Addr, val: u32) {
+        if reg == REG_STATUS {
+            self.status &= !val;
+            return;
+        }
+        if reg < REG_CHANNELS {
+            return;
+        }
+        let n = (reg - REG_CHANNELS) / 0x10;
+        let ch = &mut self.channels[n];
+        match reg & 0xF {
+            CH_SRC => ch.src = val,
+            CH_DST => ch.dst = val,
+            CH_LEN => ch.len = val,
+            CH_CTRL => {
+                if !ch.busy() && val & CTRL_ENABLE != 0 {
+                    debug!("DMA: channel {}: {} units from {:#X} to {:#X}", n, ch.len, ch.src, ch.dst);
+                }
+                ch.ctrl = val;
+            },
+            _ => (),
+        }
+    }
+
+    fn write_reg_byte(&mut self, reg: mem::Addr, addr: mem::Addr, val: mem::Byte) {
+        // Writing a single byte must not clear other pending bits.
+        let old = if reg == REG_STATUS { 0 } else { self.read_reg(reg) };
+        self.write_reg(reg, reg_set_byte(old, addr, val));
+    }
+}
+registers!(DMA);
+
+impl BusDevice for DMA {
+    fn describe(&self) -> String {
+        format!("DMA at {:#X}, {} channels", self.base, self.channels.len())
+    }
+
+    fn tick(&mut self) -> Result<(), RError> {
+        // Serve the next busy channel.
+        let count = self.channels.len();
+        let n = match (0..count).map(|i| (self.next + i) % count).find(|&n| self.channels[n].busy()) {
+            Some(n) => n,
+            None => return Ok(()),
+        };
+        self.next = (n + 1) % count;
+
+        if self.channels[n].len > 0 {
+            if let Err(e) = self.transfer(n) {
+                debug!("DMA: channel {} bus error: {}", n, e);
+                self.channels[n].ctrl &= !CTRL_ENABLE;
+                self.status |= 1 << (16 + n);
+                return Ok(());
+            }
+        }
+        if self.channels[n].len == 0 {
+            debug!("DMA: channel {} done", n);
+            self.channels[n].ctrl &= !CTRL_ENABLE;
+            self.status |= 1 << n;
+        }
+        Ok(())
+    }
+
+    fn reset(&mut self) {
+        for ch in self.channels.iter_mut() {
+            *ch = Channel::default();
+        }
+        self.status = 0;
+    }
+
+    fn interrupt(&self) -> bool {
+        self.channels.iter().enumerate().any(|(n, ch)| {
+            ch.ctrl & CTRL_IRQ != 0 && self.status & (0x10001 << n) != 0
+        })
+    }
+}
+impl MemoryBusDevice for DMA {}
+impl MemoryBusDevice32be for DMA {}
@@ -0,0 +1,293 @@
+//! Ethernet MAC.
+//!
+//! Moves frames between descriptor rings in guest memory and an `EthBackend`.
+//!
+//! A descriptor is 4 words, 32 bit big endian:
+//!
+//! - buffer address
+//! - length: for TX the frame length, for RX the buffer size,
+//!   which the MAC replaces with the received length.
+//! - flags: `DESC_OWN` hands the descriptor to the MAC, which clears it when done.
+//!   `DESC_TRUNC` is set on received frames that did not fit,
+//!   `DESC_ERR` on frames to send longer than `MAX_FRAME`, which are dropped.
+//! - reserved
+//!
+//! Registers, 32 bit big endian, relative to `base`:
+//!
+//! - `0x00` CTRL: `CTRL_*` bits.
+//! - `0x04` STATUS: pending `IRQ_*` bits, write 1 to clear.
+//! - `0x08` IRQ_MASK: `IRQ_*` bits raising the interrupt.
+//! - `0x0C` MAC_HI: upper 2 bytes of the MAC address.
+//! - `0x10` MAC_LO: lower 4 bytes of the MAC address.
+//! - `0x14` TX_RING, `0x18` TX_COUNT, `0x1C` TX_HEAD: ring address, number of
+//!   descriptors and the next descriptor the MAC looks at. Writing either of the
+//!   first two resets the head.
+//! - `0x20` RX_RING, `0x24` RX_COUNT, `0x28` RX_HEAD: same, for receiving.
+
+extern crate mem;
+
+use self::mem::errors::*;
+use bus::memorybus::{MemoryBusDevice, MemoryBusDevice32be};
+use bus::BusDevice;
+use devices::net::EthBackend;
+use errors::{Error as RError, ResultExt};
+
+use super::{Registers, reg_set_byte};
+
+const REG_CTRL: mem::Addr = 0x00;
+const REG_STATUS: mem::Addr = 0x04;
+const REG_IRQ_MASK: mem::Addr = 0x08;
+const REG_MAC_HI: mem::Addr = 0x0C;
+const REG_MAC_LO: mem::Addr = 0x10;
+const REG_TX_RING: mem::Addr = 0x14;
+const REG_TX_COUNT: mem::Addr = 0x18;
+const REG_TX_HEAD: mem::Addr = 0x1C;
+const REG_RX_RING: mem::Addr = 0x20;
+const REG_RX_COUNT: mem::Addr = 0x24;
+const REG_RX_HEAD: mem::Addr = 0x28;
+const SIZE: mem::Addr = 0x2C;
+
+pub const CTRL_TX_ENABLE: u32 = 0x01;
+pub const CTRL_RX_ENABLE: u32 = 0x02;
+/// Receive frames regardless of their destination.
+pub const CTRL_PROMISC: u32 = 0x04;
+
+pub const IRQ_RX: u32 = 0x01;
+pub const IRQ_TX: u32 = 0x02;
+/// A frame to send was dropped.
+pub const IRQ_TX_ERR: u32 = 0x04;
+
+pub const DESC_OWN: u32 = 0x80000000;
+pub const DESC_TRUNC: u32 = 0x00000001;
+pub const DESC_ERR: u32 = 0x00000002;
+const DESC_SIZE: u32 = 16;
+
+/// Longest frame sent, with header and FCS.
+pub const MAX_FRAME: mem::Addr = 1518;
+
+/// Address of descriptor `idx` of the ring at `ring`.
+/// Both come from the guest, rings running off the end wrap around.
+fn descriptor(ring: u32, idx: u32) -> mem::Addr {
+    ring.wrapping_add(idx.wrapping_mul(DESC_SIZE)) as mem::Addr
+}
+
+/// Cycles between polling the backend for new frames.
+const RX_POLL: u64 = 64;
+
+pub struct EthMAC {
+    base: mem::Addr,
+    /// Guest memory holding rings and buffers.
+    mem: Box<mem::MemoryBlock32be>,
+    backend: Box<EthBackend>,
+
+    ctrl: u32,
+    status: u32,
+    irq_mask: u32,
+    mac: [u8; 6],
+    tx_ring: u32,
+    tx_count: u32,
+    tx_head: u32,
+    rx_ring: u32,
+    rx_count: u32,
+    rx_head: u32,
+
+    /// Received frame waiting for a free descriptor.
+    pending: Option<Vec<u8>>,
+    cycle: u64,
+}
+
+impl EthMAC {
+    pub fn new(base: mem::Addr, mac: [u8; 6], mem: Box<mem::MemoryBlock32be>, backend: Box<EthBackend>) -> EthMAC {
+        EthMAC {
+            base: base,
+            mem: mem,
+            backend: backend,
+
+            ctrl: 0,
+            status: 0,
+            irq_mask: 0,
+            mac: mac,
+            tx_ring: 0,
+            tx_count: 0,
+            tx_head: 0,
+            rx_ring: 0,
+            rx_count: 0,
+            rx_head: 0,
+
+            pending: None,
+            cycle: 0,
+        }
+    }
+
+    /// Send every frame the guest handed us.
+    fn transmit(&mut self) -> Result<(), RError> {
+        while self.tx_count > 0 {
+            let desc = descriptor(self.tx_ring, self.tx_head);
+            let flags = self.mem.get32be(desc + 8)?;
+            if flags & DESC_OWN == 0 {
+                break;
+            }
+            let addr = self.mem.get32be(desc)? as mem::Addr;
+            let len = self.mem.get32be(desc + 4)? as mem::Addr;
+            if len > MAX_FRAME {
+                debug!("ETH: dropping {} byte frame", len);
+                self.mem.set32be(desc + 8, (flags & !DESC_OWN) | DESC_ERR)?;
+                self.tx_head = (self.tx_head + 1) % self.tx_count;
+                self.status |= IRQ_TX_ERR;
+                continue;
+            }
+            let mut frame = Vec::with_capacity(len);
+            for i in 0..len {
+                frame.push(self.mem.get(addr + i)?);
+            }
+            debug!("ETH: sending {} bytes", len);
+            self.backend.send(self.cycle, &frame).chain_err(|| "ETH backend failed to send")?;
+            self.mem.set32be(desc + 8, flags & !DESC_OWN)?;
+            self.tx_head = (self.tx_head + 1) % self.tx_count;
+            self.status |= IRQ_TX;
+        }
+        Ok(())
+    }
+
+    /// Is the frame for us?
+    fn accept(&self, frame: &[u8]) -> bool {
+        if frame.len() < 6 {
+            return false;
+        }
+        // Broadcast and multicast have the group bit set.
+        self.ctrl & CTRL_PROMISC != 0 || frame[0] & 1 != 0 || frame[0..6] == self.mac
+    }
+
+    /// Put received frames into free descriptors.
+    fn receive(&mut self) -> Result<(), RError> {
+        loop {
+            if self.pending.is_none() {
+                self.pending = self.backend.recv().chain_err(|| "ETH backend failed to receive")?;
+            }
+            let frame = match self.pending.take() {
+                Some(frame) => frame,
+                None => return Ok(()),
+            };
+            if !self.accept(&frame) {
+                continue;
+            }
+            if self.rx_count == 0 {
+                self.pending = Some(frame);
+                return Ok(());
+            }
+            let desc = descriptor(self.rx_ring, self.rx_head);
+            let flags = self.mem.get32be(desc + 8)?;
+            if flags & DESC_OWN == 0 {
+                // Ring is full, try again later.
+                self.pending = Some(frame);
+                return Ok(());
+            }
+            let addr = self.mem.get32be(desc)? as mem::Addr;
+            let size = self.mem.get32be(desc + 4)? as mem::Addr;
+            let len = if frame.len() > size { size } else { frame.len() };
+            for i in 0..len {
+                self.mem.set(addr + i, frame[i])?;
+            }
+            debug!("ETH: received {} bytes", frame.len());
+            let trunc = if len < frame.len() { DESC_TRUNC } else { 0 };
+            self.mem.set32be(desc + 4, len as u32)?;
+            self.mem.set32be(desc + 8, (flags & !DESC_OWN) | trunc)?;
+            self.rx_head = (self.rx_head + 1) % self.rx_count;
+            self.status |= IRQ_RX;
+        }
+    }
+}
+
+impl Registers for EthMAC {
+    fn base(&self) -> mem::Addr {
+        self.base
+    }
+
+    fn size(&self) -> mem::Addr {
+        SIZE
+    }
+
+    fn read_reg(&self, reg: mem::Addr) -> u32 {
+        match reg {
+            REG_CTRL => self.ctrl,
+            REG_STATUS => self.status,
+            REG_IRQ_MASK => self.irq_mask,
+            REG_MAC_HI => ((self.mac[0] as u32) << 8) | self.mac[1] as u32,
+            REG_MAC_LO => {
+                ((self.mac[2] as u32) << 24) | ((self.mac[3] as u32) << 16) |
+                ((self.mac[4] as u32) << 8) | self.mac[5] as u32
+            },
+            REG_TX_RING => self.tx_ring,
+            REG_TX_COUNT => self.tx_count,
+            REG_TX_HEAD => self.tx_head,
+            REG_RX_RING => self.rx_ring,
+            REG_RX_COUNT => self.rx_count,
+            REG_RX_HEAD => self.rx_head,
+            _ => 0,
+        }
+    }
+
+    fn write_reg(&mut self, reg: mem::Addr, val: u32) {
+        match reg {
+            REG_CTRL => self.ctrl = val,
+            REG_STATUS => self.status &= !val,
+            REG_IRQ_MASK => self.irq_mask = val,
+            REG_MAC_HI => {
+                self.mac[0] = (val >> 8) as u8;
+                self.mac[1] = val as u8;
+            },
+            REG_MAC_LO => {
+                self.mac[2] = (val >> 24) as u8;
+                self.mac[3] = (val >> 16) as u8;
+                self.mac[4] = (val >> 8) as u8;
+                self.mac[5] = val as u8;
+            },
+            REG_TX_RING => { self.tx_ring = val; self.tx_head = 0; },
+            REG_TX_COUNT => { self.tx_count = val; self.tx_head = 0; },
+            REG_RX_RING => { self.rx_ring = val; self.rx_head = 0; },
+            REG_RX_COUNT => { self.rx_count = val; self.rx_head = 0; },
+            _ => (),
+        }
+    }
+
+    fn write_reg_byte(&mut self, reg: mem::Addr, addr: mem::Addr, val: mem::Byte) {
+        // Writing a single byte must not clear other pending bits.
+        let old = if reg == REG_STATUS { 0 } else { self.read_reg(reg) };
+        self.write_reg(reg, reg_set_byte(old, addr, val));
+    }
+}
+registers!(EthMAC);
+
+impl BusDevice for EthMAC {
+    fn describe(&self) -> String {
+        format!("ethernet MAC at {:#X}", self.base)
+    }
+
+    fn tick(&mut self) -> Result<(), RError> {
+        self.cycle += 1;
+        if self.ctrl & CTRL_TX_ENABLE != 0 {
+            self.transmit()?;
+        }
+        if self.ctrl & CTRL_RX_ENABLE != 0 && self.cycle % RX_POLL == 0 {
+            self.receive()?;
+        }
+        Ok(())
+    }
+
+    fn reset(&mut self) {
+        self.ctrl = 0;
+        self.status = 0;
+        self.irq_mask = 0;
+        self.tx_count = 0;
+        self.tx_head = 0;
+        self.rx_count = 0;
+        self.rx_head = 0;
+        self.pending = None;
+    }
+
+    fn interrupt(&self) -> bool {
+        self.status & self.irq_mask != 0
+    }
+}
+impl MemoryBusDevice for EthMAC {}
+impl MemoryBusDevice32be for EthMAC {}
@@ -0,0 +1,82 @@
+//! Exit device.
+//!
+//! Lets the guest end the emulation with an exit code, like test programs do.
+//! Writing the code makes the next tick fail with `Exit`.
+//!
+//! Registers, 32 bit big endian, relative to `base`:
+//!
+//! - `0x00` EXIT: write the exit code. Byte writes exit too, with the code
+//!   pieced together from the written bytes.
+
+extern crate mem;
+
+use self::mem::errors::*;
+use bus::memorybus::{MemoryBusDevice, MemoryBusDevice32be};
+use bus::BusDevice;
+use errors::{Error as RError, ErrorKind as RErrorKind};
+
+use super::Registers;
+
+const SIZE: mem::Addr = 0x04;
+
+pub struct Exit {
+    base: mem::Addr,
+    code: u32,
+    pending: bool,
+}
+
+impl Exit {
+    pub fn new(base: mem::Addr) -> Exit {
+        Exit {
+            base: base,
+            code: 0,
+            pending: false,
+        }
+    }
+
+    fn exit(&mut self, code: u32) {
+        debug!("EXIT: exit code {}", code);
+        self.code = code;
+        self.pending = true;
+    }
+}
+
+impl Registers for Exit {
+    fn base(&self) -> mem::Addr {
+        self.base
+    }
+
+    fn size(&self) -> mem::Addr {
+        SIZE
+    }
+
+    fn read_reg(&self, _reg: mem::Addr) -> u32 {
+        self.code
+    }
+
+    fn write_reg(&mut self, _reg: mem::Addr, val: u32) {
+        self.exit(val);
+    }
+}
+registers!(Exit);
+
+impl BusDevice for Exit {
+    fn describe(&self) -> String {
+        format!("exit at {:#X}", self.base)
+    }
+
+    fn tick(&mut self) -> Result<(), RError> {
+        if self.pending {
+            self.pending = false;
+            bail!(RErrorKind::Exit(self.code));
+        }
+        Ok(())
+    }
+
+    fn reset(&mut self) {
+        self.code = 0;
+        self.pending = false;
+    }
+}
+impl MemoryBusDevice for Exit {}
+impl MemoryBusDevice32be for Exit {}
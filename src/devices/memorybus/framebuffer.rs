@@ -0,0 +1,346 @@
+//! Headless linear framebuffer.
+//!
+//! Nothing is displayed, frames get dumped as PPM or PNG instead,
+//! either on demand or every N frames.
+//!
+//! Layout, relative to `base`:
+//!
+//! - `0x000` WIDTH, read only.
+//! - `0x004` HEIGHT, read only.
+//! - `0x008` BPP, read only. 1, 8, 16 or 32.
+//! - `0x00C` STATUS, read only. Bit 0 is set during vertical blank.
+//! - `0x010` FRAME, read only. Number of frames completed.
+//! - `0x400` PALETTE, 256 entries of `0x00RRGGBB`.
+//! - `0x1000` VRAM, `width * height * bpp / 8` bytes.
+//!
+//! 1 bpp pixels are packed MSB first and use palette entries 0 and 1,
+//! 8 bpp pixels are palette indices, 16 bpp is RGB565 and 32 bpp is `0x00RRGGBB`.
+
+extern crate mem;
+
+use self::mem::errors::*;
+use errors::Error as RError;
+use bus::memorybus::{MemoryBusDevice, MemoryBusDevice32be};
+use bus::BusDevice;
+
+use super::{reg_byte, reg_set_byte};
+
+use std::io;
+use std::cmp;
+use std::io::prelude::*;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+const REG_WIDTH: mem::Addr = 0x000;
+const REG_HEIGHT: mem::Addr = 0x004;
+const REG_BPP: mem::Addr = 0x008;
+const REG_STATUS: mem::Addr = 0x00C;
+const REG_FRAME: mem::Addr = 0x010;
+const PALETTE: mem::Addr = 0x400;
+const VRAM: mem::Addr = 0x1000;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ImageFormat {
+    PPM,
+    PNG,
+}
+
+pub struct Framebuffer {
+    base: mem::Addr,
+    width: usize,
+    height: usize,
+    bpp: usize,
+
+    palette: [u32; 256],
+    vram: Vec<mem::Byte>,
+
+    cycle: u64,
+    cycles_per_frame: u64,
+    frame: u32,
+
+    /// Dump every n frames into dir, if set.
+    dump_every: Option<(u32, PathBuf, ImageFormat)>,
+}
+
+impl Framebuffer {
+    pub fn new(base: mem::Addr, width: usize, height: usize, bpp: usize, cycles_per_frame: u64) -> Framebuffer {
+        assert!(bpp == 1 || bpp == 8 || bpp == 16 || bpp == 32, "unsupported framebuffer depth");
+        assert!(width > 0 && height > 0, "empty framebuffer");
+        let mut palette = [0; 256];
+        // Greyscale by default, so 1 and 8 bpp are usable without setup.
+        for (i, entry) in palette.iter_mut().enumerate() {
+            let v = i as u32;
+            *entry = (v << 16) | (v << 8) | v;
+        }
+        palette[1] = 0xFFFFFF;
+        Framebuffer {
+            base: base,
+            width: width,
+            height: height,
+            bpp: bpp,
+
+            palette: palette,
+            vram: vec![0; (width * height * bpp + 7) / 8],
+
+            cycle: 0,
+            cycles_per_frame: cmp::max(cycles_per_frame, 1),
+            frame: 0,
+
+            dump_every: None,
+        }
+    }
+
+    /// Dump a frame into `dir` every `n` frames.
+    pub fn dump_every<P: AsRef<Path>>(&mut self, n: u32, dir: P, format: ImageFormat) {
+        self.dump_every = Some((cmp::max(n, 1), dir.as_ref().to_path_buf(), format));
+    }
+
+    /// Number of frames completed.
+    pub fn frame(&self) -> u32 {
+        self.frame
+    }
+
+    /// Is the display in vertical blank?
+    pub fn vsync(&self) -> bool {
+        // The last tenth of a frame is blanking, at least a cycle of it.
+        let blank = cmp::max(self.cycles_per_frame / 10, 1);
+        let pos = self.cycle % self.cycles_per_frame;
+        pos >= self.cycles_per_frame - blank
+    }
+
+    /// Current frame as packed RGB, 3 bytes per pixel.
+    pub fn rgb(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.width * self.height * 3);
+        for i in 0..(self.width * self.height) {
+            let px = match self.bpp {
+                1 => {
+                    let bit = (self.vram[i / 8] >> (7 - (i % 8))) & 1;
+                    self.palette[bit as usize]
+                },
+                8 => self.palette[self.vram[i] as usize],
+                16 => {
+                    let v = ((self.vram[i * 2] as u32) << 8) | self.vram[i * 2 + 1] as u32;
+                    let r = (v >> 11) & 0x1F;
+                    let g = (v >> 5) & 0x3F;
+                    let b = v & 0x1F;
+                    ((r << 3 | r >> 2) << 16) | ((g << 2 | g >> 4) << 8) | (b << 3 | b >> 2)
+                },
+                _ => {
+                    ((self.vram[i * 4 + 1] as u32) << 16) |
+                    ((self.vram[i * 4 + 2] as u32) << 8) |
+                    (self.vram[i * 4 + 3] as u32)
+                },
+            };
+            out.push((px >> 16) as u8);
+            out.push((px >> 8) as u8);
+            out.push(px as u8);
+        }
+        out
+    }
+
+    /// Write the current frame to `path`.
+    pub fn dump<P: AsRef<Path>>(&self, path: P, format: ImageFormat) -> Result<(), RError> {
+        let mut f = File::create(path)?;
+        match format {
+            ImageFormat::PPM => write_ppm(&mut f, self.width, self.height, &self.rgb())?,
+            ImageFormat::PNG => write_png(&mut f, self.width, self.height, &self.rgb())?,
+        }
+        Ok(())
+    }
+
+    fn read_reg(&self, reg: mem::Addr) -> u32 {
+        match reg {
+            REG_WIDTH => self.width as u32,
+            REG_HEIGHT => self.height as u32,
+            REG_BPP => self.bpp as u32,
+            REG_STATUS => if self.vsync() { 1 } else { 0 },
+            REG_FRAME => self.frame,
+            _ if reg >= PALETTE && reg < PALETTE + 256 * 4 => self.palette[(reg - PALETTE) / 4],
+            _ => 0,
+        }
+    }
+
+    fn write_reg(&mut self, reg: mem::Addr, val: u32) {
+        if reg >= PALETTE && reg < PALETTE + 256 * 4 {
+            self.palette[(reg - PALETTE) / 4] = val & 0xFFFFFF;
+        }
+    }
+
+    fn check(&self, addr: mem::Addr) -> Result<mem::Addr, Error> {
+        let max = self.base + VRAM + self.vram.len();
+        if addr < self.base {
+            bail!(ErrorKind::TooSmall(addr, self.base));
+        }
+        if addr >= max {
+            bail!(ErrorKind::TooBig(addr, max - 1));
+        }
+        Ok(addr - self.base)
+    }
+}
+
+impl mem::MemoryBlock for Framebuffer {
+    fn get_size(&self) -> usize {
+        self.base + VRAM + self.vram.len() - 1 // highest address.
+    }
+
+    fn set(&mut self, addr: mem::Addr, val: mem::Byte) -> Result<(), Error> {
+        let off = self.check(addr)?;
+        if off >= VRAM {
+            self.vram[off - VRAM] = val;
+            return Ok(());
+        }
+        let reg = off & !3;
+        let new = reg_set_byte(self.read_reg(reg), addr, val);
+        self.write_reg(reg, new);
+        Ok(())
+    }
+
+    fn get(&self, addr: mem::Addr) -> Result<mem::Byte, Error> {
+        let off = self.check(addr)?;
+        if off >= VRAM {
+            return Ok(self.vram[off - VRAM]);
+        }
+        Ok(reg_byte(self.read_reg(off & !3), addr))
+    }
+}
+
+impl mem::MemoryBlock32be for Framebuffer {
+    fn set32be(&mut self, addr: mem::Addr, val: u32) -> Result<(), Error> {
+        let off = self.check(addr)?;
+        if off >= VRAM {
+            for i in 0..4 {
+                mem::MemoryBlock::set(self, addr + i, reg_byte(val, i))?;
+            }
+            return Ok(());
+        }
+        if off & 3 != 0 {
+            bail!(ErrorKind::InvalidAddr(addr));
+        }
+        self.write_reg(off, val);
+        Ok(())
+    }
+
+    fn get32be(&self, addr: mem::Addr) -> Result<u32, Error> {
+        let off = self.check(addr)?;
+        if off >= VRAM {
+            let mut val = 0;
+            for i in 0..4 {
+                val = (val << 8) | mem::MemoryBlock::get(self, addr + i)? as u32;
+            }
+            return Ok(val);
+        }
+        if off & 3 != 0 {
+            bail!(ErrorKind::InvalidAddr(addr));
+        }
+        Ok(self.read_reg(off))
+    }
+}
+
+impl BusDevice for Framebuffer {
+    fn describe(&self) -> String {
+        format!("framebuffer at {:#X}, {}x{} at {} bpp", self.base, self.width, self.height, self.bpp)
+    }
+
+    fn tick(&mut self) -> Result<(), RError> {
+        self.cycle += 1;
+        if self.cycle % self.cycles_per_frame != 0 {
+            return Ok(());
+        }
+        self.frame = self.frame.wrapping_add(1);
+
+        let dump = match self.dump_every {
+            Some((n, ref dir, format)) if self.frame % n == 0 => {
+                let ext = match format {
+                    ImageFormat::PPM => "ppm",
+                    ImageFormat::PNG => "png",
+                };
+                Some((dir.join(format!("frame{:06}.{}", self.frame, ext)), format))
+            },
+            _ => None,
+        };
+        if let Some((path, format)) = dump {
+            debug!("FB: dumping frame {} to {}", self.frame, path.display());
+            if let Err(e) = self.dump(&path, format) {
+                writeln!(io::stderr(), "FB: failed to dump frame to {}: {}", path.display(), e).unwrap();
+            }
+        }
+        Ok(())
+    }
+}
+impl MemoryBusDevice for Framebuffer {}
+impl MemoryBusDevice32be for Framebuffer {}
+
+// Image writers
+
+fn write_ppm<W: Write>(w: &mut W, width: usize, height: usize, rgb: &[u8]) -> io::Result<()> {
+    write!(w, "P6\n{} {}\n255\n", width, height)?;
+    w.write_all(rgb)
+}
+
+fn crc32(crc: u32, data: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for b in data {
+        crc ^= *b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB88320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn png_chunk<W: Write>(w: &mut W, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    let len = data.len() as u32;
+    w.write_all(&[(len >> 24) as u8, (len >> 16) as u8, (len >> 8) as u8, len as u8])?;
+    w.write_all(kind)?;
+    w.write_all(data)?;
+    let crc = crc32(crc32(0, kind), data);
+    w.write_all(&[(crc >> 24) as u8, (crc >> 16) as u8, (crc >> 8) as u8, crc as u8])
+}
+
+/// Uncompressed PNG, using stored deflate blocks.
+/// Big, but golden images get compared, not looked at.
+fn write_png<W: Write>(w: &mut W, width: usize, height: usize, rgb: &[u8]) -> io::Result<()> {
+    // PNG has no empty images.
+    if width == 0 || height == 0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "empty image"));
+    }
+    w.write_all(b"\x89PNG\r\n\x1a\n")?;
+
+    let (wi, he) = (width as u32, height as u32);
+    let ihdr = [
+        (wi >> 24) as u8, (wi >> 16) as u8, (wi >> 8) as u8, wi as u8,
+        (he >> 24) as u8, (he >> 16) as u8, (he >> 8) as u8, he as u8,
+        8, 2, 0, 0, 0, // 8 bit RGB, no interlace
+    ];
+    png_chunk(w, b"IHDR", &ihdr)?;
+
+    // Scanlines, each prefixed by filter type 0.
+    let mut raw = Vec::with_capacity((width * 3 + 1) * height);
+    for line in rgb.chunks(width * 3) {
+        raw.push(0);
+        raw.extend_from_slice(line);
+    }
+
+    // zlib stream
+    let mut z = vec![0x78, 0x01];
+    let mut blocks = raw.chunks(0xFFFF).peekable();
+    if blocks.peek().is_none() {
+        z.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = if blocks.peek().is_none() { 1 } else { 0 };
+        let len = block.len() as u16;
+        z.extend_from_slice(&[last, len as u8, (len >> 8) as u8, !len as u8, (!len >> 8) as u8]);
+        z.extend_from_slice(block);
+    }
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in &raw {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    let adler = (b << 16) | a;
+    z.extend_from_slice(&[(adler >> 24) as u8, (adler >> 16) as u8, (adler >> 8) as u8, adler as u8]);
+    png_chunk(w, b"IDAT", &z)?;
+
+    png_chunk(w, b"IEND", &[])
+}
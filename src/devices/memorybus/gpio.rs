@@ -0,0 +1,203 @@
+//! GPIO block.
+//!
+//! 32 pins, each either an input or an output.
+//! The host can drive input pins at given cycles and
+//! gets a log of every change on the output pins.
+//!
+//! Registers, 32 bit big endian, relative to `base`:
+//!
+//! - `0x00` DIR: 1 = output, 0 = input.
+//! - `0x04` IN: levels of all pins. Read only.
+//! - `0x08` OUT: output latch.
+//! - `0x0C` RISE: interrupt on rising edge enable mask.
+//! - `0x10` FALL: interrupt on falling edge enable mask.
+//! - `0x14` STATUS: pending edge interrupts, write 1 to clear.
+
+extern crate mem;
+
+use self::mem::errors::*;
+use bus::memorybus::{MemoryBusDevice, MemoryBusDevice32be};
+use bus::BusDevice;
+use errors::Error as RError;
+
+use super::{Registers, reg_set_byte};
+
+const REG_DIR: mem::Addr = 0x00;
+const REG_IN: mem::Addr = 0x04;
+const REG_OUT: mem::Addr = 0x08;
+const REG_RISE: mem::Addr = 0x0C;
+const REG_FALL: mem::Addr = 0x10;
+const REG_STATUS: mem::Addr = 0x14;
+const SIZE: mem::Addr = 0x18;
+
+/// A change of a pin's level at a given cycle.
+#[derive(Debug, PartialEq, Clone)]
+pub struct PinChange {
+    pub cycle: u64,
+    pub pin: u8,
+    pub level: bool,
+}
+
+pub struct GPIO {
+    base: mem::Addr,
+    cycle: u64,
+
+    dir: u32,
+    input: u32,
+    out: u32,
+    rise: u32,
+    fall: u32,
+    status: u32,
+
+    /// Scheduled input changes, sorted by cycle.
+    script: Vec<PinChange>,
+    /// Recorded output changes.
+    log: Vec<PinChange>,
+}
+
+impl GPIO {
+    pub fn new(base: mem::Addr) -> GPIO {
+        GPIO {
+            base: base,
+            cycle: 0,
+
+            dir: 0,
+            input: 0,
+            out: 0,
+            rise: 0,
+            fall: 0,
+            status: 0,
+
+            script: vec![],
+            log: vec![],
+        }
+    }
+
+    /// Drive input `pin` to `level` once `cycle` is reached.
+    pub fn drive(&mut self, cycle: u64, pin: u8, level: bool) {
+        let change = PinChange { cycle: cycle, pin: pin & 0x1F, level: level };
+        let pos = self.script.iter().position(|c| c.cycle > cycle).unwrap_or(self.script.len());
+        self.script.insert(pos, change);
+    }
+
+    /// Recorded output pin changes, oldest first.
+    pub fn log(&self) -> &[PinChange] {
+        &self.log
+    }
+
+    /// Current level of all pins, as the outside world sees them.
+    pub fn pins(&self) -> u32 {
+        (self.out & self.dir) | (self.input & !self.dir)
+    }
+
+    /// Current cycle.
+    pub fn cycle(&self) -> u64 {
+        self.cycle
+    }
+
+    /// Log every output pin that changed.
+    fn record(&mut self, before: u32, after: u32) {
+        let changed = before ^ after;
+        for pin in 0..32 {
+            if (changed >> pin) & 1 != 0 {
+                debug!("GPIO: pin {} -> {}", pin, (after >> pin) & 1);
+                self.log.push(PinChange {
+                    cycle: self.cycle,
+                    pin: pin as u8,
+                    level: (after >> pin) & 1 != 0,
+                });
+            }
+        }
+    }
+
+    /// Apply a new input level, latching edge interrupts.
+    fn apply(&mut self, input: u32) {
+        let old = self.input & !self.dir;
+        let new = input & !self.dir;
+        self.status |= (!old & new & self.rise) | (old & !new & self.fall);
+        self.input = input;
+    }
+}
+
+impl Registers for GPIO {
+    fn base(&self) -> mem::Addr {
+        self.base
+    }
+
+    fn size(&self) -> mem::Addr {
+        SIZE
+    }
+
+    fn read_reg(&self, reg: mem::Addr) -> u32 {
+        match reg {
+            REG_DIR => self.dir,
+            REG_IN => self.pins(),
+            REG_OUT => self.out,
+            REG_RISE => self.rise,
+            REG_FALL => self.fall,
+            REG_STATUS => self.status,
+            _ => 0,
+        }
+    }
+
+    fn write_reg(&mut self, reg: mem::Addr, val: u32) {
+        let before = self.out & self.dir;
+        match reg {
+            REG_DIR => self.dir = val,
+            REG_OUT => self.out = val,
+            REG_RISE => self.rise = val,
+            REG_FALL => self.fall = val,
+            REG_STATUS => self.status &= !val,
+            _ => (),
+        }
+        let after = self.out & self.dir;
+        self.record(before, after);
+    }
+
+    fn write_reg_byte(&mut self, reg: mem::Addr, addr: mem::Addr, val: mem::Byte) {
+        // Writing a single byte must not clear other pending bits.
+        let old = if reg == REG_STATUS { 0 } else { self.read_reg(reg) };
+        self.write_reg(reg, reg_set_byte(old, addr, val));
+    }
+}
+registers!(GPIO);
+
+impl BusDevice for GPIO {
+    fn describe(&self) -> String {
+        format!("GPIO at {:#X}", self.base)
+    }
+
+    fn tick(&mut self) -> Result<(), RError> {
+        self.cycle += 1;
+        let mut input = self.input;
+        while !self.script.is_empty() && self.script[0].cycle <= self.cycle {
+            let change = self.script.remove(0);
+            if change.level {
+                input |= 1 << change.pin;
+            } else {
+                input &= !(1 << change.pin);
+            }
+        }
+        if input != self.input {
+            self.apply(input);
+        }
+        Ok(())
+    }
+
+    fn reset(&mut self) {
+        // Log the outputs going away, as writes to OUT and DIR do.
+        let before = self.out & self.dir;
+        self.record(before, 0);
+        self.dir = 0;
+        self.out = 0;
+        self.rise = 0;
+        self.fall = 0;
+        self.status = 0;
+    }
+
+    fn interrupt(&self) -> bool {
+        self.status != 0
+    }
+}
+impl MemoryBusDevice for GPIO {}
+impl MemoryBusDevice32be for GPIO {}
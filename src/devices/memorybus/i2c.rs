@@ -0,0 +1,144 @@
+//! I2C master.
+//!
+//! Drives an `I2CBus`, one command at a time. Commands complete instantly.
+//!
+//! Registers, 32 bit big endian, relative to `base`:
+//!
+//! - `0x00` DATA: byte to send, or the last byte received.
+//! - `0x04` CMD: write only, a combination of the `CMD_*` bits.
+//!   `START | WRITE` sends DATA as the address byte.
+//! - `0x08` STATUS: read only, `STATUS_*` bits.
+
+extern crate mem;
+
+use self::mem::errors::*;
+use bus::memorybus::{MemoryBusDevice, MemoryBusDevice32be};
+use bus::i2c::I2CBus;
+use bus::BusDevice;
+use errors::Error as RError;
+
+use super::{Registers, reg_set_byte};
+
+const REG_DATA: mem::Addr = 0x00;
+const REG_CMD: mem::Addr = 0x04;
+const REG_STATUS: mem::Addr = 0x08;
+const SIZE: mem::Addr = 0x0C;
+
+pub const CMD_START: u32 = 0x01;
+pub const CMD_STOP: u32 = 0x02;
+pub const CMD_WRITE: u32 = 0x04;
+pub const CMD_READ: u32 = 0x08;
+/// Answer a read with NACK instead of ACK, for the last byte.
+pub const CMD_NACK: u32 = 0x10;
+
+/// Last byte was not acknowledged, by the slave or, with `CMD_NACK`, by us.
+pub const STATUS_NACK: u32 = 0x01;
+/// Between START and STOP.
+pub const STATUS_BUSY: u32 = 0x02;
+
+pub struct I2CMaster {
+    base: mem::Addr,
+    bus: I2CBus,
+
+    data: u8,
+    status: u32,
+}
+
+impl I2CMaster {
+    pub fn new(base: mem::Addr, bus: I2CBus) -> I2CMaster {
+        I2CMaster {
+            base: base,
+            bus: bus,
+
+            data: 0,
+            status: 0,
+        }
+    }
+
+    /// The bus with all the slaves.
+    pub fn bus(&mut self) -> &mut I2CBus {
+        &mut self.bus
+    }
+
+    fn command(&mut self, cmd: u32) {
+        if cmd & CMD_START != 0 {
+            self.status |= STATUS_BUSY;
+            let ack = self.bus.start(self.data >> 1, self.data & 1 != 0);
+            self.set_ack(ack);
+        } else if cmd & CMD_WRITE != 0 {
+            let ack = self.bus.write(self.data);
+            self.set_ack(ack);
+        } else if cmd & CMD_READ != 0 {
+            // Reads are acknowledged by us, not the slave.
+            let ack = cmd & CMD_NACK == 0;
+            self.data = self.bus.read(ack);
+            self.set_ack(ack);
+        }
+        if cmd & CMD_STOP != 0 {
+            self.bus.stop();
+            self.status &= !STATUS_BUSY;
+        }
+    }
+
+    fn set_ack(&mut self, ack: bool) {
+        if ack {
+            self.status &= !STATUS_NACK;
+        } else {
+            self.status |= STATUS_NACK;
+        }
+    }
+}
+
+impl Registers for I2CMaster {
+    fn base(&self) -> mem::Addr {
+        self.base
+    }
+
+    fn size(&self) -> mem::Addr {
+        SIZE
+    }
+
+    fn read_reg(&self, reg: mem::Addr) -> u32 {
+        match reg {
+            REG_DATA => self.data as u32,
+            REG_STATUS => self.status,
+            _ => 0,
+        }
+    }
+
+    fn write_reg(&mut self, reg: mem::Addr, val: u32) {
+        match reg {
+            REG_DATA => self.data = val as u8,
+            REG_CMD => self.command(val),
+            _ => (),
+        }
+    }
+
+    fn write_reg_byte(&mut self, reg: mem::Addr, addr: mem::Addr, val: mem::Byte) {
+        // Only the lowest byte carries anything, don't trigger commands twice.
+        if addr & 3 != 3 {
+            return;
+        }
+        self.write_reg(reg, reg_set_byte(0, addr, val));
+    }
+}
+registers!(I2CMaster);
+
+impl BusDevice for I2CMaster {
+    fn describe(&self) -> String {
+        format!("I2C master at {:#X}", self.base)
+    }
+
+    fn tick(&mut self) -> Result<(), RError> {
+        self.bus.tick();
+        Ok(())
+    }
+
+    fn reset(&mut self) {
+        self.bus.stop();
+        self.data = 0;
+        self.status = 0;
+    }
+}
+impl MemoryBusDevice for I2CMaster {}
+impl MemoryBusDevice32be for I2CMaster {}
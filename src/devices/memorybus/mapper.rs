@@ -0,0 +1,251 @@
+//! Bank switching memory mapper.
+//!
+//! Maps windows of the address space onto banks of a bigger backing block.
+//! A window of size `n` set to bank `b` shows backing bytes `b * n` to `(b + 1) * n`,
+//! mirrored if the backing block is smaller than that.
+//!
+//! Banks get selected either by bank select registers, one 32 bit big endian
+//! word per window starting at `regs`, or cartridge style, by writes into
+//! selection ranges, which usually overlap the read only windows.
+//!
+//! There are presets for common cartridge types, see `uxrom` and `mbc1`.
+
+extern crate mem;
+
+use self::mem::errors::*;
+use bus::memorybus::{MemoryBusDevice, MemoryBusDevice32be};
+use bus::BusDevice;
+
+use super::reg_byte;
+
+#[derive(Debug, Clone)]
+struct Window {
+    base: mem::Addr,
+    size: mem::Addr,
+    bank: usize,
+    /// Bank after reset.
+    initial: usize,
+    fixed: bool,
+}
+
+/// Writes to `from..to` select the bank of `window`, masked with `mask`.
+#[derive(Debug, Clone)]
+struct Select {
+    from: mem::Addr,
+    to: mem::Addr,
+    window: usize,
+    mask: usize,
+}
+
+pub struct Mapper<T: mem::MemoryBlock32be> {
+    backing: T,
+    writable: bool,
+    regs: Option<mem::Addr>,
+
+    windows: Vec<Window>,
+    selects: Vec<Select>,
+}
+
+impl<T: mem::MemoryBlock32be> Mapper<T> {
+    /// Mapper without windows. `writable` makes the windows RAM instead of ROM.
+    pub fn new(backing: T, writable: bool) -> Mapper<T> {
+        Mapper {
+            backing: backing,
+            writable: writable,
+            regs: None,
+
+            windows: vec![],
+            selects: vec![],
+        }
+    }
+
+    /// Add a switchable window, showing `bank` initially.
+    pub fn window(mut self, base: mem::Addr, size: mem::Addr, bank: usize) -> Mapper<T> {
+        self.windows.push(Window { base: base, size: size, bank: bank, initial: bank, fixed: false });
+        self
+    }
+
+    /// Add a window always showing `bank`.
+    pub fn fixed_window(mut self, base: mem::Addr, size: mem::Addr, bank: usize) -> Mapper<T> {
+        self.windows.push(Window { base: base, size: size, bank: bank, initial: bank, fixed: true });
+        self
+    }
+
+    /// Map bank select registers at `addr`.
+    pub fn registers(mut self, addr: mem::Addr) -> Mapper<T> {
+        self.regs = Some(addr);
+        self
+    }
+
+    /// Writes to `from..to` select the bank of window number `window`.
+    pub fn select_range(mut self, from: mem::Addr, to: mem::Addr, window: usize, mask: usize) -> Mapper<T> {
+        self.selects.push(Select { from: from, to: to, window: window, mask: mask });
+        self
+    }
+
+    /// UxROM style: 32 KiB at `base`, the lower 16 KiB switchable, the upper
+    /// fixed to the last bank. Writing anywhere in it selects the lower bank.
+    pub fn uxrom(base: mem::Addr, backing: T) -> Mapper<T> {
+        let banks = backing.get_size() / 0x4000;
+        let last = if banks > 0 { banks - 1 } else { 0 };
+        Mapper::new(backing, false)
+            .window(base, 0x4000, 0)
+            .fixed_window(base + 0x4000, 0x4000, last)
+            .select_range(base, base + 0x8000, 0, 0xFF)
+    }
+
+    /// MBC1 style, ROM only: 32 KiB at `base`, the lower 16 KiB fixed to bank 0,
+    /// the upper switchable. Writes to `0x2000` to `0x3FFF` select its bank.
+    pub fn mbc1(base: mem::Addr, backing: T) -> Mapper<T> {
+        Mapper::new(backing, false)
+            .fixed_window(base, 0x4000, 0)
+            .window(base + 0x4000, 0x4000, 1)
+            .select_range(base + 0x2000, base + 0x4000, 1, 0x1F)
+    }
+
+    /// Bank shown by window number `window`.
+    pub fn bank(&self, window: usize) -> usize {
+        self.windows[window].bank
+    }
+
+    /// Switch window number `window` to `bank`, unless it is fixed.
+    pub fn switch(&mut self, window: usize, bank: usize) {
+        if let Some(w) = self.windows.get_mut(window) {
+            if !w.fixed {
+                debug!("MAPPER: window {:#X} -> bank {}", w.base, bank);
+                w.bank = bank;
+            }
+        }
+    }
+
+    /// The backing block.
+    pub fn backing(&mut self) -> &mut T {
+        &mut self.backing
+    }
+
+    /// Backing address of `addr`, if it is in a window.
+    fn translate(&self, addr: mem::Addr) -> Option<mem::Addr> {
+        let size = self.backing.get_size();
+        if size == 0 {
+            return None;
+        }
+        self.windows.iter()
+            .find(|w| addr >= w.base && addr < w.base + w.size)
+            .map(|w| (w.bank * w.size + (addr - w.base)) % size)
+    }
+
+    /// Bank select register number, if `addr` is one.
+    fn register(&self, addr: mem::Addr) -> Option<usize> {
+        match self.regs {
+            Some(regs) if addr >= regs && addr < regs + self.windows.len() * 4 => Some((addr - regs) / 4),
+            _ => None,
+        }
+    }
+
+    /// Handle writes to selection ranges.
+    fn select(&mut self, addr: mem::Addr, val: u32) -> bool {
+        let sel = self.selects.iter().find(|s| addr >= s.from && addr < s.to).cloned();
+        match sel {
+            Some(s) => {
+                self.switch(s.window, val as usize & s.mask);
+                true
+            },
+            None => false,
+        }
+    }
+
+    fn miss(&self, addr: mem::Addr) -> Error {
+        let min = self.windows.iter().map(|w| w.base).min().unwrap_or(0);
+        let max = mem::MemoryBlock::get_size(self);
+        if addr < min {
+            return ErrorKind::TooSmall(addr, min).into();
+        }
+        if addr >= max {
+            return ErrorKind::TooBig(addr, max - 1).into();
+        }
+        ErrorKind::InvalidAddr(addr).into()
+    }
+}
+
+impl<T: mem::MemoryBlock32be> mem::MemoryBlock for Mapper<T> {
+    fn get_size(&self) -> usize {
+        let regs_end = self.regs.map_or(0, |r| r + self.windows.len() * 4);
+        self.windows.iter().map(|w| w.base + w.size).fold(regs_end, |a, b| if a > b { a } else { b })
+    }
+
+    fn set(&mut self, addr: mem::Addr, val: mem::Byte) -> Result<(), Error> {
+        if let Some(reg) = self.register(addr) {
+            // The lowest byte selects.
+            if addr & 3 == 3 {
+                self.switch(reg, val as usize);
+            }
+            return Ok(());
+        }
+        if self.select(addr, val as u32) {
+            return Ok(());
+        }
+        match self.translate(addr) {
+            Some(off) if self.writable => self.backing.set(off, val),
+            Some(_) => bail!(ErrorKind::ReadOnly(addr, "write to mapped ROM")),
+            None => Err(self.miss(addr)),
+        }
+    }
+
+    fn get(&self, addr: mem::Addr) -> Result<mem::Byte, Error> {
+        if let Some(reg) = self.register(addr) {
+            return Ok(reg_byte(self.windows[reg].bank as u32, addr));
+        }
+        match self.translate(addr) {
+            Some(off) => self.backing.get(off),
+            None => Err(self.miss(addr)),
+        }
+    }
+}
+
+impl<T: mem::MemoryBlock32be> mem::MemoryBlock32be for Mapper<T> {
+    fn set32be(&mut self, addr: mem::Addr, val: u32) -> Result<(), Error> {
+        if let Some(reg) = self.register(addr) {
+            self.switch(reg, val as usize);
+            return Ok(());
+        }
+        if self.select(addr, val) {
+            return Ok(());
+        }
+        match self.translate(addr) {
+            // Words must not straddle windows.
+            Some(off) if self.writable && self.translate(addr + 3) == Some(off + 3) => self.backing.set32be(off, val),
+            Some(_) if self.writable => bail!(ErrorKind::InvalidAddr(addr)),
+            Some(_) => bail!(ErrorKind::ReadOnly(addr, "write to mapped ROM")),
+            None => Err(self.miss(addr)),
+        }
+    }
+
+    fn get32be(&self, addr: mem::Addr) -> Result<u32, Error> {
+        if let Some(reg) = self.register(addr) {
+            return Ok(self.windows[reg].bank as u32);
+        }
+        match self.translate(addr) {
+            Some(off) if self.translate(addr + 3) == Some(off + 3) => self.backing.get32be(off),
+            Some(_) => bail!(ErrorKind::InvalidAddr(addr)),
+            None => Err(self.miss(addr)),
+        }
+    }
+}
+
+impl<T: mem::MemoryBlock32be> BusDevice for Mapper<T> {
+    fn describe(&self) -> String {
+        format!("mapper, {} windows", self.windows.len())
+    }
+
+    fn reset(&mut self) {
+        for w in self.windows.iter_mut() {
+            w.bank = w.initial;
+        }
+    }
+}
+impl<T: mem::MemoryBlock32be> MemoryBusDevice for Mapper<T> {}
+impl<T: mem::MemoryBlock32be> MemoryBusDevice32be for Mapper<T> {
+    fn peek32be(&self, addr: mem::Addr) -> Result<u32, Error> {
+        mem::MemoryBlock32be::get32be(self, addr)
+    }
+}
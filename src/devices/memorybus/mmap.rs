@@ -0,0 +1,185 @@
+//! Memory backed by a memory mapped host file.
+//!
+//! Maps like plain memory at address 0, just as `MemVector` does.
+//! Large images don't need to be copied in first and persistent
+//! mappings double as battery backed SRAM or NVRAM.
+
+extern crate mem;
+extern crate memmap;
+extern crate byteorder;
+
+use self::mem::errors::*;
+use self::memmap::{MmapMut, MmapOptions};
+use self::byteorder::{ByteOrder, BigEndian, LittleEndian};
+use bus::memorybus::{MemoryBusDevice, MemoryBusDevice32be, MemoryBusDevice32le};
+use bus::BusDevice;
+use errors::Error as RError;
+
+use std::io::prelude::*;
+use std::fs::{File, OpenOptions};
+use std::path::Path;
+
+/// How writes relate to the file.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum MapMode {
+    /// Writes end up in the file, which gets created or grown as needed.
+    Persistent,
+    /// The file is only read, writes stay private to the mapping.
+    CopyOnWrite,
+}
+
+pub struct MemMap {
+    map: MmapMut,
+    mode: MapMode,
+}
+
+impl MemMap {
+    /// Map `size` bytes of the file at `path`.
+    ///
+    /// Copy on write mappings of files shorter than `size` can't be mapped directly,
+    /// as mapped pages past the end of a file can't be accessed.
+    /// They get read into anonymous memory instead, the rest is zero.
+    /// Copy on write mappings of files longer than `size` fail, as they wouldn't fit.
+    pub fn open<P: AsRef<Path>>(path: P, size: usize, mode: MapMode) -> Result<MemMap, RError> {
+        let map = match mode {
+            MapMode::Persistent => {
+                let f = OpenOptions::new().read(true).write(true).create(true).open(path.as_ref())?;
+                if f.metadata()?.len() < size as u64 {
+                    f.set_len(size as u64)?;
+                }
+                unsafe { MmapOptions::new().len(size).map_mut(&f)? }
+            },
+            MapMode::CopyOnWrite => {
+                let mut f = File::open(path.as_ref())?;
+                let len = f.metadata()?.len();
+                if len > size as u64 {
+                    bail!("{} doesn't fit into {:#X} bytes", path.as_ref().display(), size);
+                }
+                if len == size as u64 {
+                    unsafe { MmapOptions::new().len(size).map_copy(&f)? }
+                } else {
+                    let mut map = MmapMut::map_anon(size)?;
+                    let mut pos = 0;
+                    loop {
+                        match f.read(&mut map[pos..])? {
+                            0 => break,
+                            n => pos += n,
+                        }
+                    }
+                    map
+                }
+            },
+        };
+        Ok(MemMap {
+            map: map,
+            mode: mode,
+        })
+    }
+
+    /// Anonymous memory, zeroed, not backed by any file.
+    pub fn anon(size: usize) -> Result<MemMap, RError> {
+        Ok(MemMap {
+            map: MmapMut::map_anon(size)?,
+            mode: MapMode::CopyOnWrite,
+        })
+    }
+
+    pub fn mode(&self) -> MapMode {
+        self.mode
+    }
+
+    /// The mapped memory.
+    pub fn as_slice(&self) -> &[u8] {
+        &self.map
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        &mut self.map
+    }
+
+    fn check(&self, addr: mem::Addr, len: usize) -> Result<(), Error> {
+        if addr + len > self.map.len() {
+            bail!(ErrorKind::TooBig(addr, self.map.len() - 1));
+        }
+        Ok(())
+    }
+}
+
+impl mem::MemoryBlock for MemMap {
+    fn get_size(&self) -> usize {
+        self.map.len()
+    }
+
+    fn set(&mut self, addr: mem::Addr, val: mem::Byte) -> Result<(), Error> {
+        self.check(addr, 1)?;
+        self.map[addr] = val;
+        Ok(())
+    }
+
+    fn get(&self, addr: mem::Addr) -> Result<mem::Byte, Error> {
+        self.check(addr, 1)?;
+        Ok(self.map[addr])
+    }
+
+    fn delete(&mut self, from: mem::Addr, to: mem::Addr) -> Result<(), Error> {
+        self.check(to, 1)?;
+        for b in &mut self.map[from..to + 1] {
+            *b = 0;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        if self.mode == MapMode::Persistent {
+            self.map.flush().chain_err(|| "failed to flush mapping")?;
+        }
+        Ok(())
+    }
+}
+
+impl mem::MemoryBlock32be for MemMap {
+    fn set32be(&mut self, addr: mem::Addr, val: u32) -> Result<(), Error> {
+        self.check(addr, 4)?;
+        BigEndian::write_u32(&mut self.map[addr..addr + 4], val);
+        Ok(())
+    }
+
+    fn get32be(&self, addr: mem::Addr) -> Result<u32, Error> {
+        self.check(addr, 4)?;
+        Ok(BigEndian::read_u32(&self.map[addr..addr + 4]))
+    }
+}
+
+impl mem::MemoryBlock32le for MemMap {
+    fn set32le(&mut self, addr: mem::Addr, val: u32) -> Result<(), Error> {
+        self.check(addr, 4)?;
+        LittleEndian::write_u32(&mut self.map[addr..addr + 4], val);
+        Ok(())
+    }
+
+    fn get32le(&self, addr: mem::Addr) -> Result<u32, Error> {
+        self.check(addr, 4)?;
+        Ok(LittleEndian::read_u32(&self.map[addr..addr + 4]))
+    }
+}
+
+impl Drop for MemMap {
+    fn drop(&mut self) {
+        if self.mode == MapMode::Persistent {
+            let _ = self.map.flush();
+        }
+    }
+}
+
+impl BusDevice for MemMap {
+    fn describe(&self) -> String {
+        format!("memory, {:#X} bytes, {:?}", self.map.len(), self.mode)
+    }
+}
+impl MemoryBusDevice for MemMap {}
+impl MemoryBusDevice32be for MemMap {
+    fn peek32be(&self, addr: mem::Addr) -> Result<u32, Error> {
+        mem::MemoryBlock32be::get32be(self, addr)
+    }
+}
+impl MemoryBusDevice32le for MemMap {}
@@ -1,4 +1,128 @@
 // Just a stub.
+
+/// Implement `mem::MemoryBlock` and `mem::MemoryBlock32be` for a device with `Registers`.
+/// Extra `mem::MemoryBlock` methods, like `flush`, go in braces after the type.
+/// Defined before the devices, so they can use it.
+macro_rules! registers {
+    ($t:ty) => { registers!($t, {}); };
+    ($t:ty, { $($extra:item)* }) => {
+        impl mem::MemoryBlock for $t {
+            fn get_size(&self) -> usize {
+                super::reg_top(self)
+            }
+
+            fn set(&mut self, addr: mem::Addr, val: mem::Byte) -> Result<(), Error> {
+                let reg = super::reg_offset(self, addr)? & !3;
+                super::Registers::write_reg_byte(self, reg, addr, val);
+                Ok(())
+            }
+
+            fn get(&self, addr: mem::Addr) -> Result<mem::Byte, Error> {
+                let reg = super::reg_offset(self, addr)? & !3;
+                Ok(super::reg_byte(super::Registers::read_reg(self, reg), addr))
+            }
+
+            $($extra)*
+        }
+
+        impl mem::MemoryBlock32be for $t {
+            fn set32be(&mut self, addr: mem::Addr, val: u32) -> Result<(), Error> {
+                let reg = super::reg_word(self, addr)?;
+                super::Registers::write_reg(self, reg, val);
+                Ok(())
+            }
+
+            fn get32be(&self, addr: mem::Addr) -> Result<u32, Error> {
+                let reg = super::reg_word(self, addr)?;
+                Ok(super::Registers::read_reg(self, reg))
+            }
+        }
+    };
+}
+
 pub mod sio;
-pub mod null;
+pub mod unmapped;
+pub mod gpio;
+pub mod framebuffer;
+pub mod i2c;
+pub mod rtc;
+pub mod watchdog;
+pub mod rng;
+pub mod eth;
+pub mod audio;
+pub mod dma;
+pub mod rom;
+pub mod mapper;
+pub mod mmap;
+pub mod overlay;
+pub mod exit;
+pub mod phi;
+pub mod zpuino;
+
+extern crate mem;
+
+use self::mem::errors::*;
+
+// Register helpers.
+// Most devices expose 32 bit big endian registers,
+// but the CPU might poke single bytes of them.
+
+/// Get the byte lane `addr` points at out of a register.
+#[inline]
+fn reg_byte(reg: u32, addr: mem::Addr) -> mem::Byte {
+    (reg >> ((3 - (addr & 3)) * 8)) as mem::Byte
+}
+
+/// Replace the byte lane `addr` points at in a register.
+#[inline]
+fn reg_set_byte(reg: u32, addr: mem::Addr, val: mem::Byte) -> u32 {
+    let shift = (3 - (addr & 3)) * 8;
+    (reg & !(0xFF << shift)) | ((val as u32) << shift)
+}
+
+/// A block of 32 bit big endian registers, made memory by `registers!`.
+/// Accesses outside it fail with `TooSmall` or `TooBig`,
+/// word accesses have to be aligned.
+trait Registers {
+    /// First address.
+    fn base(&self) -> mem::Addr;
+
+    /// Bytes taken.
+    fn size(&self) -> mem::Addr;
+
+    fn read_reg(&self, reg: mem::Addr) -> u32;
+
+    fn write_reg(&mut self, reg: mem::Addr, val: u32);
+
+    /// Write the byte lane `addr` points at of `reg`.
+    /// Merges it into the register, override for registers acting on writes.
+    fn write_reg_byte(&mut self, reg: mem::Addr, addr: mem::Addr, val: mem::Byte) {
+        let new = reg_set_byte(self.read_reg(reg), addr, val);
+        self.write_reg(reg, new);
+    }
+}
+
+/// Highest address of the registers, for `get_size`.
+fn reg_top<T: Registers>(dev: &T) -> usize {
+    dev.base() + dev.size() - 1
+}
+
+/// Offset of `addr` into the registers.
+fn reg_offset<T: Registers>(dev: &T, addr: mem::Addr) -> Result<mem::Addr, Error> {
+    if addr < dev.base() {
+        bail!(ErrorKind::TooSmall(addr, dev.base()));
+    }
+    if addr >= dev.base() + dev.size() {
+        bail!(ErrorKind::TooBig(addr, reg_top(dev)));
+    }
+    Ok(addr - dev.base())
+}
 
+/// Offset of the register a word access at `addr` hits, which has to be aligned.
+fn reg_word<T: Registers>(dev: &T, addr: mem::Addr) -> Result<mem::Addr, Error> {
+    let reg = reg_offset(dev, addr)?;
+    if reg & 3 != 0 {
+        bail!(ErrorKind::InvalidAddr(addr));
+    }
+    Ok(reg)
+}
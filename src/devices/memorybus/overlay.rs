@@ -0,0 +1,183 @@
+//! Copy on write overlay memory.
+//!
+//! Sits on top of a shared base image and only keeps copies of the pages written to.
+//! Many overlays can share one base, say a booted machine, each forking off of it.
+//! Snapshots share unchanged pages with the overlay, so taking one and
+//! rolling back to it only costs the pages changed in between.
+
+extern crate mem;
+
+use self::mem::errors::*;
+use bus::memorybus::{MemoryBusDevice, MemoryBusDevice32be};
+use bus::BusDevice;
+
+use std::collections::HashMap;
+use std::rc::Rc;
+
+pub const PAGE_SIZE: usize = 0x1000;
+
+type Pages = HashMap<usize, Rc<Vec<u8>>>;
+
+/// Pages of an overlay at some point in time.
+#[derive(Clone)]
+pub struct Snapshot {
+    pages: Pages,
+}
+
+pub struct Overlay<T: mem::MemoryBlock32be> {
+    base: Rc<T>,
+    pages: Pages,
+}
+
+impl<T: mem::MemoryBlock32be> Overlay<T> {
+    pub fn new(base: Rc<T>) -> Overlay<T> {
+        Overlay {
+            base: base,
+            pages: HashMap::new(),
+        }
+    }
+
+    pub fn base(&self) -> &Rc<T> {
+        &self.base
+    }
+
+    /// Another overlay on the same base, starting with the same contents.
+    pub fn fork(&self) -> Overlay<T> {
+        Overlay {
+            base: self.base.clone(),
+            pages: self.pages.clone(),
+        }
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot { pages: self.pages.clone() }
+    }
+
+    /// Go back to the contents at the time of `snap`.
+    pub fn rollback(&mut self, snap: &Snapshot) {
+        self.pages = snap.pages.clone();
+    }
+
+    /// Throw away all writes, going back to the base.
+    pub fn discard(&mut self) {
+        self.pages.clear();
+    }
+
+    /// Number of pages written to.
+    pub fn dirty_pages(&self) -> usize {
+        self.pages.len()
+    }
+
+    fn check(&self, addr: mem::Addr) -> Result<(), Error> {
+        let size = self.base.get_size();
+        if size == 0 {
+            bail!(ErrorKind::InvalidAddr(addr));
+        }
+        if addr >= size {
+            bail!(ErrorKind::TooBig(addr, size - 1));
+        }
+        Ok(())
+    }
+
+    /// Page of `addr`, copied from the base if it wasn't yet.
+    fn page_mut(&mut self, addr: mem::Addr) -> Result<&mut Vec<u8>, Error> {
+        let n = addr / PAGE_SIZE;
+        if !self.pages.contains_key(&n) {
+            let start = n * PAGE_SIZE;
+            let end = ::std::cmp::min(start + PAGE_SIZE, self.base.get_size());
+            let mut page = Vec::with_capacity(PAGE_SIZE);
+            for a in start..end {
+                page.push(self.base.get(a)?);
+            }
+            page.resize(PAGE_SIZE, 0);
+            self.pages.insert(n, Rc::new(page));
+        }
+        // Shared with a snapshot or fork? Copy it.
+        Ok(Rc::make_mut(self.pages.get_mut(&n).unwrap()))
+    }
+
+    /// Does a word at `addr` straddle pages?
+    fn straddles(addr: mem::Addr) -> bool {
+        addr % PAGE_SIZE > PAGE_SIZE - 4
+    }
+}
+
+impl<T: mem::MemoryBlock32be> mem::MemoryBlock for Overlay<T> {
+    fn get_size(&self) -> usize {
+        self.base.get_size()
+    }
+
+    fn set(&mut self, addr: mem::Addr, val: mem::Byte) -> Result<(), Error> {
+        self.check(addr)?;
+        self.page_mut(addr)?[addr % PAGE_SIZE] = val;
+        Ok(())
+    }
+
+    fn get(&self, addr: mem::Addr) -> Result<mem::Byte, Error> {
+        self.check(addr)?;
+        match self.pages.get(&(addr / PAGE_SIZE)) {
+            Some(page) => Ok(page[addr % PAGE_SIZE]),
+            None => self.base.get(addr),
+        }
+    }
+
+    fn delete(&mut self, from: mem::Addr, to: mem::Addr) -> Result<(), Error> {
+        self.check(to)?;
+        for addr in from..to + 1 {
+            mem::MemoryBlock::set(self, addr, 0)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl<T: mem::MemoryBlock32be> mem::MemoryBlock32be for Overlay<T> {
+    fn set32be(&mut self, addr: mem::Addr, val: u32) -> Result<(), Error> {
+        self.check(addr + 3)?;
+        if Self::straddles(addr) {
+            for i in 0..4 {
+                mem::MemoryBlock::set(self, addr + i, (val >> ((3 - i) * 8)) as mem::Byte)?;
+            }
+            return Ok(());
+        }
+        let off = addr % PAGE_SIZE;
+        let page = self.page_mut(addr)?;
+        for i in 0..4 {
+            page[off + i] = (val >> ((3 - i) * 8)) as u8;
+        }
+        Ok(())
+    }
+
+    fn get32be(&self, addr: mem::Addr) -> Result<u32, Error> {
+        self.check(addr + 3)?;
+        match self.pages.get(&(addr / PAGE_SIZE)) {
+            Some(page) if !Self::straddles(addr) => {
+                let off = addr % PAGE_SIZE;
+                Ok(page[off..off + 4].iter().fold(0, |v, b| (v << 8) | *b as u32))
+            },
+            None if !Self::straddles(addr) => self.base.get32be(addr),
+            _ => {
+                let mut val = 0;
+                for i in 0..4 {
+                    val = (val << 8) | mem::MemoryBlock::get(self, addr + i)? as u32;
+                }
+                Ok(val)
+            },
+        }
+    }
+}
+
+impl<T: mem::MemoryBlock32be> BusDevice for Overlay<T> {
+    fn describe(&self) -> String {
+        format!("overlay, {} dirty pages", self.dirty_pages())
+    }
+}
+impl<T: mem::MemoryBlock32be> MemoryBusDevice for Overlay<T> {}
+impl<T: mem::MemoryBlock32be> MemoryBusDevice32be for Overlay<T> {
+    fn peek32be(&self, addr: mem::Addr) -> Result<u32, Error> {
+        mem::MemoryBlock32be::get32be(self, addr)
+    }
+}
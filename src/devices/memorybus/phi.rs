@@ -0,0 +1,142 @@
+//! I/O block of the Phi platform, minus the UART, which `SIOTerm` does.
+//!
+//! Covers the whole I/O page, unused addresses read 0 and ignore writes,
+//! like on the real thing.
+//!
+//! Registers, 32 bit big endian, relative to `base`:
+//!
+//! - `0x14` TIMER: write bit 0 to reset the counter, bit 1 to sample it.
+//!   Resetting also acknowledges the timer interrupt.
+//! - `0x18` COUNTER: read only, cycles counted up to the last sample.
+//! - `0x1C` MHZ: read only, clock frequency in MHz.
+//! - `0x20` INTR_STATUS: pending interrupts, write 1 to clear.
+//!   Bit 0 is the timer.
+//! - `0x24` INTR_MASK: enabled interrupts.
+//! - `0x28` TIMER_PERIOD: cycles between timer interrupts, 0 turns them off.
+//!
+//! The BSP of zpu-gcc only uses TIMER, COUNTER and MHZ, the interrupt registers are our own.
+
+extern crate mem;
+
+use self::mem::errors::*;
+use bus::memorybus::{MemoryBusDevice, MemoryBusDevice32be};
+use bus::BusDevice;
+use errors::Error as RError;
+
+use super::{Registers, reg_set_byte};
+
+const REG_TIMER: mem::Addr = 0x14;
+const REG_COUNTER: mem::Addr = 0x18;
+const REG_MHZ: mem::Addr = 0x1C;
+const REG_INTR_STATUS: mem::Addr = 0x20;
+const REG_INTR_MASK: mem::Addr = 0x24;
+const REG_TIMER_PERIOD: mem::Addr = 0x28;
+const SIZE: mem::Addr = 0x100;
+
+const TIMER_RESET: u32 = 0x01;
+const TIMER_SAMPLE: u32 = 0x02;
+
+pub const INTR_TIMER: u32 = 0x01;
+
+pub struct PhiIO {
+    base: mem::Addr,
+    mhz: u32,
+
+    counter: u64,
+    sample: u32,
+    period: u32,
+    status: u32,
+    mask: u32,
+}
+
+impl PhiIO {
+    pub fn new(base: mem::Addr, mhz: u32) -> PhiIO {
+        PhiIO {
+            base: base,
+            mhz: mhz,
+
+            counter: 0,
+            sample: 0,
+            period: 0,
+            status: 0,
+            mask: 0,
+        }
+    }
+}
+
+impl Registers for PhiIO {
+    fn base(&self) -> mem::Addr {
+        self.base
+    }
+
+    fn size(&self) -> mem::Addr {
+        SIZE
+    }
+
+    fn read_reg(&self, reg: mem::Addr) -> u32 {
+        match reg {
+            REG_COUNTER => self.sample,
+            REG_MHZ => self.mhz,
+            REG_INTR_STATUS => self.status,
+            REG_INTR_MASK => self.mask,
+            REG_TIMER_PERIOD => self.period,
+            _ => 0,
+        }
+    }
+
+    fn write_reg(&mut self, reg: mem::Addr, val: u32) {
+        match reg {
+            REG_TIMER => {
+                if val & TIMER_RESET != 0 {
+                    self.counter = 0;
+                    self.status &= !INTR_TIMER;
+                }
+                if val & TIMER_SAMPLE != 0 {
+                    self.sample = self.counter as u32;
+                }
+            },
+            REG_INTR_STATUS => self.status &= !val,
+            REG_INTR_MASK => self.mask = val,
+            REG_TIMER_PERIOD => self.period = val,
+            _ => (),
+        }
+    }
+
+    fn write_reg_byte(&mut self, reg: mem::Addr, addr: mem::Addr, val: mem::Byte) {
+        // Commands and clearing interrupts only act on the bits written.
+        let old = match reg {
+            REG_TIMER | REG_INTR_STATUS => 0,
+            _ => self.read_reg(reg),
+        };
+        self.write_reg(reg, reg_set_byte(old, addr, val));
+    }
+}
+registers!(PhiIO);
+
+impl BusDevice for PhiIO {
+    fn describe(&self) -> String {
+        format!("Phi I/O at {:#X}", self.base)
+    }
+
+    fn tick(&mut self) -> Result<(), RError> {
+        self.counter += 1;
+        if self.period != 0 && self.counter % self.period as u64 == 0 {
+            self.status |= INTR_TIMER;
+        }
+        Ok(())
+    }
+
+    fn reset(&mut self) {
+        self.counter = 0;
+        self.sample = 0;
+        self.period = 0;
+        self.status = 0;
+        self.mask = 0;
+    }
+
+    fn interrupt(&self) -> bool {
+        self.status & self.mask != 0
+    }
+}
+impl MemoryBusDevice for PhiIO {}
+impl MemoryBusDevice32be for PhiIO {}
@@ -0,0 +1,152 @@
+//! Hardware random number generator.
+//!
+//! Either hands out host entropy, or numbers from a seeded PRNG
+//! for runs that need to be reproducible.
+//!
+//! Registers, 32 bit big endian, relative to `base`:
+//!
+//! - `0x00` DATA: read only, a fresh random number on every read.
+//!   Byte reads get a fresh random byte each.
+//! - `0x04` STATUS: read only, bit 0 is set when data is ready. Always.
+
+extern crate mem;
+
+use self::mem::errors::*;
+use bus::memorybus::{MemoryBusDevice, MemoryBusDevice32be};
+use bus::BusDevice;
+use errors::Error as RError;
+
+use super::reg_byte;
+
+use std::io;
+use std::io::prelude::*;
+use std::fs::File;
+use std::cell::{Cell, RefCell};
+
+const REG_DATA: mem::Addr = 0x00;
+const REG_STATUS: mem::Addr = 0x04;
+const SIZE: mem::Addr = 0x08;
+
+enum Source {
+    Host(RefCell<File>),
+    Seeded(Cell<u64>),
+}
+
+pub struct RNG {
+    base: mem::Addr,
+    source: Source,
+    seed: Option<u64>,
+}
+
+impl RNG {
+    /// RNG returning host entropy.
+    pub fn new_host(base: mem::Addr) -> Result<RNG, RError> {
+        let f = File::open("/dev/urandom")?;
+        Ok(RNG {
+            base: base,
+            source: Source::Host(RefCell::new(f)),
+            seed: None,
+        })
+    }
+
+    /// RNG returning a reproducible sequence for `seed`.
+    /// Tells the seed on stderr, so the run can be repeated.
+    pub fn new_seeded(base: mem::Addr, seed: u64) -> RNG {
+        writeln!(io::stderr(), "RNG seed: {:#X}", seed).unwrap();
+        RNG {
+            base: base,
+            source: Source::Seeded(Cell::new(seed)),
+            seed: Some(seed),
+        }
+    }
+
+    /// Seed, if seeded.
+    pub fn seed(&self) -> Option<u64> {
+        self.seed
+    }
+
+    /// Next random number.
+    pub fn next(&self) -> Result<u32, Error> {
+        match self.source {
+            Source::Host(ref f) => {
+                let mut buf = [0 as mem::Byte; 4];
+                if f.borrow_mut().read_exact(&mut buf).is_err() {
+                    bail!(ErrorKind::HardwareFault(self.base, "RNG device failed to read host entropy."));
+                }
+                Ok(((buf[0] as u32) << 24) | ((buf[1] as u32) << 16) | ((buf[2] as u32) << 8) | buf[3] as u32)
+            },
+            Source::Seeded(ref state) => {
+                // splitmix64
+                let s = state.get().wrapping_add(0x9E3779B97F4A7C15);
+                state.set(s);
+                let mut z = s;
+                z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+                z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+                Ok((z ^ (z >> 31)) as u32)
+            },
+        }
+    }
+
+    fn check(&self, addr: mem::Addr) -> Result<mem::Addr, Error> {
+        if addr < self.base {
+            bail!(ErrorKind::TooSmall(addr, self.base));
+        }
+        if addr >= self.base + SIZE {
+            bail!(ErrorKind::TooBig(addr, self.base + SIZE - 1));
+        }
+        Ok(addr - self.base)
+    }
+}
+
+/// A seed from host entropy, to be logged and replayed.
+pub fn random_seed() -> Result<u64, RError> {
+    let mut buf = [0 as mem::Byte; 8];
+    File::open("/dev/urandom")?.read_exact(&mut buf)?;
+    Ok(buf.iter().fold(0, |acc, b| (acc << 8) | *b as u64))
+}
+
+impl mem::MemoryBlock for RNG {
+    fn get_size(&self) -> usize {
+        self.base + SIZE - 1 // highest address.
+    }
+
+    fn set(&mut self, addr: mem::Addr, _val: mem::Byte) -> Result<(), Error> {
+        self.check(addr)?;
+        bail!(ErrorKind::ReadOnly(addr, "RNG registers are read only."))
+    }
+
+    fn get(&self, addr: mem::Addr) -> Result<mem::Byte, Error> {
+        let reg = self.check(addr)? & !3;
+        match reg {
+            REG_DATA => Ok(self.next()? as mem::Byte),
+            _ => Ok(reg_byte(1, addr)),
+        }
+    }
+}
+
+impl mem::MemoryBlock32be for RNG {
+    fn set32be(&mut self, addr: mem::Addr, _val: u32) -> Result<(), Error> {
+        self.check(addr)?;
+        bail!(ErrorKind::ReadOnly(addr, "RNG registers are read only."))
+    }
+
+    fn get32be(&self, addr: mem::Addr) -> Result<u32, Error> {
+        let reg = self.check(addr)?;
+        match reg {
+            REG_DATA => self.next(),
+            REG_STATUS => Ok(1),
+            _ => bail!(ErrorKind::InvalidAddr(addr)),
+        }
+    }
+}
+
+impl BusDevice for RNG {
+    fn describe(&self) -> String {
+        match self.seed {
+            Some(seed) => format!("RNG at {:#X}, seed {:#X}", self.base, seed),
+            None => format!("RNG at {:#X}", self.base),
+        }
+    }
+}
+impl MemoryBusDevice for RNG {}
+impl MemoryBusDevice32be for RNG {}
@@ -0,0 +1,122 @@
+//! Read only memory.
+//!
+//! Wraps any memory block, mapping it at `base` and refusing writes to it.
+//! What a refused write does is up to the `WritePolicy`.
+//!
+//! Put it in front of RAM covering the same addresses on a bus:
+//! reads get answered by the ROM first, ignored writes end up in the shadowed RAM.
+
+extern crate mem;
+
+use self::mem::errors::*;
+use bus::memorybus::{MemoryBusDevice, MemoryBusDevice32be};
+use bus::BusDevice;
+
+use std::io;
+use std::io::prelude::*;
+
+/// What to do about writes.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum WritePolicy {
+    /// Drop them, returning `ReadOnly`, which a bus ignores.
+    Ignore,
+    /// Fail with a hardware fault, stopping the CPU.
+    Fault,
+    /// Like `Ignore`, but tell about it on stderr.
+    Log,
+}
+
+pub struct ROM<T: mem::MemoryBlock32be> {
+    base: mem::Addr,
+    inner: T,
+    policy: WritePolicy,
+}
+
+impl<T: mem::MemoryBlock32be> ROM<T> {
+    pub fn new(base: mem::Addr, inner: T, policy: WritePolicy) -> ROM<T> {
+        ROM {
+            base: base,
+            inner: inner,
+            policy: policy,
+        }
+    }
+
+    /// The wrapped block, writable.
+    pub fn inner(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    fn check(&self, addr: mem::Addr) -> Result<mem::Addr, Error> {
+        if addr < self.base {
+            bail!(ErrorKind::TooSmall(addr, self.base));
+        }
+        let size = self.inner.get_size();
+        if size == 0 {
+            bail!(ErrorKind::InvalidAddr(addr));
+        }
+        if addr > self.base + size - 1 {
+            bail!(ErrorKind::TooBig(addr, self.base + size - 1));
+        }
+        Ok(addr - self.base)
+    }
+
+    fn refuse(&self, addr: mem::Addr, val: u32) -> Result<(), Error> {
+        match self.policy {
+            WritePolicy::Ignore => (),
+            WritePolicy::Log => {
+                writeln!(io::stderr(), "ROM: ignored write of {:#X} to {:#X}", val, addr).unwrap();
+            },
+            WritePolicy::Fault => bail!(ErrorKind::HardwareFault(addr, "write to ROM")),
+        }
+        bail!(ErrorKind::ReadOnly(addr, "write to ROM"))
+    }
+}
+
+impl<T: mem::MemoryBlock32be> mem::MemoryBlock for ROM<T> {
+    fn get_size(&self) -> usize {
+        match self.inner.get_size() {
+            // Nothing mapped, check() turns every address down.
+            0 => self.base,
+            size => self.base + size - 1, // highest address.
+        }
+    }
+
+    fn set(&mut self, addr: mem::Addr, val: mem::Byte) -> Result<(), Error> {
+        self.check(addr)?;
+        self.refuse(addr, val as u32)
+    }
+
+    fn get(&self, addr: mem::Addr) -> Result<mem::Byte, Error> {
+        let off = self.check(addr)?;
+        self.inner.get(off)
+    }
+
+    fn delete(&mut self, from: mem::Addr, _to: mem::Addr) -> Result<(), Error> {
+        self.check(from)?;
+        bail!(ErrorKind::ReadOnly(from, "write to ROM"))
+    }
+}
+
+impl<T: mem::MemoryBlock32be> mem::MemoryBlock32be for ROM<T> {
+    fn set32be(&mut self, addr: mem::Addr, val: u32) -> Result<(), Error> {
+        self.check(addr)?;
+        self.refuse(addr, val)
+    }
+
+    fn get32be(&self, addr: mem::Addr) -> Result<u32, Error> {
+        let off = self.check(addr)?;
+        self.inner.get32be(off)
+    }
+}
+
+impl<T: mem::MemoryBlock32be> BusDevice for ROM<T> {
+    fn describe(&self) -> String {
+        format!("ROM at {:#X}, writes: {:?}", self.base, self.policy)
+    }
+}
+impl<T: mem::MemoryBlock32be> MemoryBusDevice for ROM<T> {}
+impl<T: mem::MemoryBlock32be> MemoryBusDevice32be for ROM<T> {
+    fn peek32be(&self, addr: mem::Addr) -> Result<u32, Error> {
+        mem::MemoryBlock32be::get32be(self, addr)
+    }
+}
@@ -0,0 +1,139 @@
+//! Real time clock.
+//!
+//! Tells the guest the time of day, from the machine's `TimeSource`.
+//! The time gets latched into the registers by writing `CTRL_LATCH` to CTRL,
+//! so multi-word reads are consistent.
+//!
+//! Registers, 32 bit big endian, relative to `base`:
+//!
+//! - `0x00` CTRL: write only, `CTRL_*` bits.
+//! - `0x04` SECONDS_HI, `0x08` SECONDS_LO: seconds since the unix epoch.
+//!   Writable, `CTRL_SET` makes them the new time.
+//! - `0x0C` NANOS: nanoseconds.
+//! - `0x10` YEAR, `0x14` MONTH, `0x18` DAY, `0x1C` HOUR, `0x20` MINUTE,
+//!   `0x24` SECOND, `0x28` WEEKDAY (0 is sunday). Read only.
+
+extern crate mem;
+
+use self::mem::errors::*;
+use bus::memorybus::{MemoryBusDevice, MemoryBusDevice32be};
+use bus::BusDevice;
+use errors::Error as RError;
+use clock::{Clock, DateTime, TimeSource};
+
+use super::{Registers, reg_set_byte};
+
+const REG_CTRL: mem::Addr = 0x00;
+const REG_SECONDS_HI: mem::Addr = 0x04;
+const REG_SECONDS_LO: mem::Addr = 0x08;
+const REG_NANOS: mem::Addr = 0x0C;
+const REG_YEAR: mem::Addr = 0x10;
+const REG_MONTH: mem::Addr = 0x14;
+const REG_DAY: mem::Addr = 0x18;
+const REG_HOUR: mem::Addr = 0x1C;
+const REG_MINUTE: mem::Addr = 0x20;
+const REG_SECOND: mem::Addr = 0x24;
+const REG_WEEKDAY: mem::Addr = 0x28;
+const SIZE: mem::Addr = 0x2C;
+
+/// Latch the current time.
+pub const CTRL_LATCH: u32 = 0x01;
+/// Set the time from SECONDS_HI and SECONDS_LO.
+pub const CTRL_SET: u32 = 0x02;
+
+pub struct RTC {
+    base: mem::Addr,
+    clock: Clock,
+
+    secs: i64,
+    nanos: u32,
+    date: DateTime,
+}
+
+impl RTC {
+    pub fn new(base: mem::Addr, source: TimeSource) -> RTC {
+        let mut rtc = RTC {
+            base: base,
+            clock: Clock::new(source),
+
+            secs: 0,
+            nanos: 0,
+            date: DateTime::from_unix(0),
+        };
+        rtc.latch();
+        rtc
+    }
+
+    fn latch(&mut self) {
+        let (secs, nanos) = self.clock.now();
+        self.secs = secs;
+        self.nanos = nanos;
+        self.date = DateTime::from_unix(secs);
+    }
+}
+
+impl Registers for RTC {
+    fn base(&self) -> mem::Addr {
+        self.base
+    }
+
+    fn size(&self) -> mem::Addr {
+        SIZE
+    }
+
+    fn read_reg(&self, reg: mem::Addr) -> u32 {
+        match reg {
+            REG_SECONDS_HI => (self.secs >> 32) as u32,
+            REG_SECONDS_LO => self.secs as u32,
+            REG_NANOS => self.nanos,
+            REG_YEAR => self.date.year as u32,
+            REG_MONTH => self.date.month as u32,
+            REG_DAY => self.date.day as u32,
+            REG_HOUR => self.date.hour as u32,
+            REG_MINUTE => self.date.minute as u32,
+            REG_SECOND => self.date.second as u32,
+            REG_WEEKDAY => self.date.weekday as u32,
+            _ => 0,
+        }
+    }
+
+    fn write_reg(&mut self, reg: mem::Addr, val: u32) {
+        match reg {
+            REG_CTRL => {
+                if val & CTRL_SET != 0 {
+                    debug!("RTC: time set to {}", self.secs);
+                    self.clock.set(self.secs);
+                }
+                if val & (CTRL_LATCH | CTRL_SET) != 0 {
+                    self.latch();
+                }
+            },
+            REG_SECONDS_HI => self.secs = ((val as i64) << 32) | (self.secs & 0xFFFFFFFF),
+            REG_SECONDS_LO => self.secs = (self.secs & !0xFFFFFFFF) | val as i64,
+            _ => (),
+        }
+    }
+
+    fn write_reg_byte(&mut self, reg: mem::Addr, addr: mem::Addr, val: mem::Byte) {
+        // CTRL acts on its lowest byte only.
+        if reg == REG_CTRL && addr & 3 != 3 {
+            return;
+        }
+        let new = reg_set_byte(self.read_reg(reg), addr, val);
+        self.write_reg(reg, new);
+    }
+}
+registers!(RTC);
+
+impl BusDevice for RTC {
+    fn describe(&self) -> String {
+        format!("RTC at {:#X}", self.base)
+    }
+
+    fn tick(&mut self) -> Result<(), RError> {
+        self.clock.tick();
+        Ok(())
+    }
+}
+impl MemoryBusDevice for RTC {}
+impl MemoryBusDevice32be for RTC {}
@@ -5,9 +5,9 @@ extern crate mem;
 use self::mem::errors::*;
 use bus::memorybus::{MemoryBusDevice, MemoryBusDevice32be};
 use bus::BusDevice;
+use devices::serial::{SerialBackend, Stdio};
 
-use std::io;
-use std::io::prelude::*;
+use std::cell::RefCell;
 use std::cmp;
 
 /// Basic Terminal I/O.
@@ -17,6 +17,8 @@ use std::cmp;
 /// To read a char, `get` `addr_read`.
 /// To print a char, `set` `addr_write`.
 /// Plus a ton more, because ZPU reasons.
+///
+/// Talks to the terminal, unless given another `SerialBackend`.
 pub struct SIOTerm {
     base: mem::Addr,
     addr_read: mem::Addr,
@@ -27,6 +29,7 @@ pub struct SIOTerm {
     addr_write_max: mem::Addr,
     min: mem::Addr,
     max: mem::Addr,
+    serial: RefCell<Box<SerialBackend>>,
 }
 
 impl SIOTerm {
@@ -43,6 +46,34 @@ impl SIOTerm {
             addr_write_max: addr_write,
             min: cmp::min(addr_read - 3, addr_write - 3),
             max: cmp::max(addr_read, addr_write),
+            serial: RefCell::new(Box::new(Stdio)),
+        }
+    }
+
+    /// Use `serial` instead of the terminal.
+    pub fn with_serial(mut self, serial: Box<SerialBackend>) -> SIOTerm {
+        self.serial = RefCell::new(serial);
+        self
+    }
+
+    fn write(&self, addr: mem::Addr, val: mem::Byte) -> Result<(), Error> {
+        match self.serial.borrow_mut().write(val) {
+            Ok(()) => Ok(()),
+            Err(_) => bail!(ErrorKind::HardwareFault(addr, "SIO device failed to write.")),
+        }
+    }
+
+    /// Read a byte, if there is one.
+    fn read(&self, addr: mem::Addr) -> Result<Option<mem::Byte>, Error> {
+        match self.serial.borrow_mut().read() {
+            Ok(val) => {
+                debug!("SIO: read char {:?}", val.map(|c| c as char));
+                Ok(val)
+            },
+            Err(_) => {
+                debug!("SIO: hw fail");
+                bail!(ErrorKind::HardwareFault(addr, "SIO device failed to read."))
+            },
         }
     }
 }
@@ -55,12 +86,7 @@ impl mem::MemoryBlock for SIOTerm {
     fn set(&mut self, addr: mem::Addr, val: mem::Byte) -> Result<(), Error> {
         if addr == self.addr_write {
             debug!("SIO: got write @ {:#X}: {}", addr, val as char);
-            let mut buf = [0 as mem::Byte, 1];
-            buf[0] = val;
-            return match io::stdout().write(&buf) {
-                Ok(_) => Ok(()),
-                Err(_) => bail!(ErrorKind::HardwareFault(addr, "SIO device failed to read from stdin."))
-            }
+            return self.write(addr, val);
         }
 
         if addr >= self.addr_write_min && addr <= self.addr_write_max {
@@ -80,17 +106,7 @@ impl mem::MemoryBlock for SIOTerm {
     fn get(&self, addr: mem::Addr) -> Result<mem::Byte, Error> {
         if addr == self.addr_read {
             debug!("SIO: got read @ {:#X}", addr);
-            let mut buf = [0 as mem::Byte; 1];
-            return match io::stdin().read(&mut buf) {
-                Ok(_) => {
-                    debug!("SIO: read char {}", buf[0] as char);
-                    Ok(buf[0])
-                },
-                Err(_) => {
-                    debug!("SIO: hw fail");
-                    bail!(ErrorKind::HardwareFault(addr, "SIO device failed to write to stdout."))
-                },
-            }
+            return Ok(self.read(addr)?.unwrap_or(0));
         } else if addr == self.addr_write - 1 {
             debug!("SIO: got read @ {:#X}, returning 1", addr);
             return Ok(1) // fake remaining bytes.
@@ -123,12 +139,7 @@ impl mem::MemoryBlock32be for SIOTerm {
     fn set32be(&mut self, addr: mem::Addr, val: u32) -> Result<(), Error> {
         if addr == self.base {
             debug!("SIO: got write @ {:#X}: {}", addr, (val & 0xFF) as mem::Byte as char);
-            let mut buf = [0 as mem::Byte, 1];
-            buf[0] = (val & 0xFF) as mem::Byte;
-            return match io::stdout().write(&buf) {
-                Ok(_) => Ok(()),
-                Err(_) => bail!(ErrorKind::HardwareFault(addr, "SIO device failed to write to stdout."))
-            }
+            return self.write(addr, (val & 0xFF) as mem::Byte);
         }
 
         if addr >= self.addr_write_min && addr <= self.addr_write_max {
@@ -152,17 +163,8 @@ impl mem::MemoryBlock32be for SIOTerm {
         }
         if addr == (self.base + 4) { // read addr
             debug!("SIO: got read @ {:#X}", addr);
-            let mut buf = [0 as mem::Byte; 1];
-            return match io::stdin().read(&mut buf) {
-                Ok(_) => {
-                    debug!("SIO: read char {}", buf[0] as char);
-                    Ok(buf[0] as u32 | 0x100)
-                },
-                Err(_) => {
-                    debug!("SIO: hw fail");
-                    bail!(ErrorKind::HardwareFault(addr, "SIO device failed to read from stdin."))
-                },
-            }
+            // Bit 8 tells there was data.
+            return Ok(self.read(addr)?.map_or(0, |c| c as u32 | 0x100));
         }
 
         if addr < self.min {
@@ -176,6 +178,10 @@ impl mem::MemoryBlock32be for SIOTerm {
     }
 }
 
-impl BusDevice for SIOTerm {}
+impl BusDevice for SIOTerm {
+    fn describe(&self) -> String {
+        format!("SIOTerm at {:#X}", self.base)
+    }
+}
 impl MemoryBusDevice for SIOTerm {}
 impl MemoryBusDevice32be for SIOTerm {}
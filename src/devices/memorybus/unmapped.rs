@@ -0,0 +1,103 @@
+//! Unmapped address space.
+//!
+//! Claims every address. Put it last on a bus, so it gets
+//! whatever no other device took: wild pointers, mostly.
+//! Buses know it's a catch-all, and don't hand it writes other devices took.
+//! What happens then depends on the `UnmappedPolicy`.
+
+extern crate mem;
+
+use self::mem::errors::*;
+use bus::memorybus::{MemoryBusDevice, MemoryBusDevice32be};
+use bus::{BusDevice, Initiator};
+
+use std::io;
+use std::io::prelude::*;
+use std::usize;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum UnmappedPolicy {
+    /// Reads return the open bus value in every byte, writes vanish.
+    OpenBus(mem::Byte),
+    /// Accesses fail with a hardware fault, stopping the CPU.
+    Fault,
+    /// Like `OpenBus`, but tell about every access on stderr.
+    Log(mem::Byte),
+}
+
+pub struct Unmapped {
+    policy: UnmappedPolicy,
+    initiator: Option<Initiator>,
+}
+
+impl Unmapped {
+    pub fn new(policy: UnmappedPolicy) -> Unmapped {
+        Unmapped {
+            policy: policy,
+            initiator: None,
+        }
+    }
+
+    /// Consumes everything, contains nothing. Forever.
+    pub fn null() -> Unmapped {
+        Unmapped::new(UnmappedPolicy::OpenBus(0))
+    }
+
+    /// Include the PC of the CPU in logs.
+    pub fn with_initiator(mut self, initiator: Initiator) -> Unmapped {
+        self.initiator = Some(initiator);
+        self
+    }
+
+    fn access(&self, what: &str, addr: mem::Addr) -> Result<mem::Byte, Error> {
+        match self.policy {
+            UnmappedPolicy::OpenBus(val) => Ok(val),
+            UnmappedPolicy::Fault => bail!(ErrorKind::HardwareFault(addr, "access to unmapped address")),
+            UnmappedPolicy::Log(val) => {
+                let stderr = &mut io::stderr();
+                match self.initiator.as_ref().and_then(|i| i.pc()) {
+                    Some(pc) => writeln!(stderr, "Unmapped: {} {:#X} at PC {:#X}", what, addr, pc).unwrap(),
+                    None => writeln!(stderr, "Unmapped: {} {:#X}", what, addr).unwrap(),
+                }
+                Ok(val)
+            },
+        }
+    }
+}
+
+impl mem::MemoryBlock for Unmapped {
+    fn get_size(&self) -> mem::Addr {
+        usize::MAX
+    }
+
+    fn get(&self, addr: mem::Addr) -> Result<mem::Byte, Error> {
+        self.access("read8", addr)
+    }
+
+    fn set(&mut self, addr: mem::Addr, _value: mem::Byte) -> Result<(), Error> {
+        self.access("write8", addr).map(|_| ())
+    }
+}
+
+impl mem::MemoryBlock32be for Unmapped {
+    fn get32be(&self, addr: mem::Addr) -> Result<u32, Error> {
+        let val = self.access("read32", addr)? as u32;
+        Ok(val | (val << 8) | (val << 16) | (val << 24))
+    }
+
+    fn set32be(&mut self, addr: mem::Addr, _value: u32) -> Result<(), Error> {
+        self.access("write32", addr).map(|_| ())
+    }
+}
+
+impl BusDevice for Unmapped {
+    fn describe(&self) -> String {
+        format!("unmapped, {:?}", self.policy)
+    }
+}
+impl MemoryBusDevice for Unmapped {}
+impl MemoryBusDevice32be for Unmapped {
+    fn catch_all(&self) -> bool {
+        true
+    }
+}
@@ -0,0 +1,162 @@
+//! Watchdog timer.
+//!
+//! Once enabled, firmware has to kick it within `timeout` cycles.
+//! If it does not, the watchdog either asks for a reset of the machine
+//! or halts it, by failing its tick with `WatchdogReset` or `WatchdogHalt`.
+//!
+//! Registers, 32 bit big endian, relative to `base`:
+//!
+//! - `0x00` CTRL: bit 0 enables the watchdog. Cleared by reset.
+//! - `0x04` TIMEOUT: cycles between kicks.
+//! - `0x08` KICK: write anything to kick.
+//! - `0x0C` COUNT: read only, cycles left until expiry.
+//! - `0x10` STATUS: bit 0 is set if the last reset was caused by the watchdog.
+//!   Survives resets, write 1 to clear.
+
+extern crate mem;
+
+use self::mem::errors::*;
+use bus::memorybus::{MemoryBusDevice, MemoryBusDevice32be};
+use bus::BusDevice;
+use errors::{Error as RError, ErrorKind as RErrorKind};
+
+use super::{Registers, reg_set_byte};
+
+const REG_CTRL: mem::Addr = 0x00;
+const REG_TIMEOUT: mem::Addr = 0x04;
+const REG_KICK: mem::Addr = 0x08;
+const REG_COUNT: mem::Addr = 0x0C;
+const REG_STATUS: mem::Addr = 0x10;
+const SIZE: mem::Addr = 0x14;
+
+const CTRL_ENABLE: u32 = 0x01;
+const STATUS_WDT_RESET: u32 = 0x01;
+
+/// What happens when the watchdog expires.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum WatchdogAction {
+    Reset,
+    Halt,
+}
+
+pub struct Watchdog {
+    base: mem::Addr,
+    action: WatchdogAction,
+
+    ctrl: u32,
+    timeout: u32,
+    status: u32,
+
+    cycle: u64,
+    last_kick: u64,
+}
+
+impl Watchdog {
+    pub fn new(base: mem::Addr, timeout: u32, action: WatchdogAction) -> Watchdog {
+        Watchdog {
+            base: base,
+            action: action,
+
+            ctrl: 0,
+            timeout: timeout,
+            status: 0,
+
+            cycle: 0,
+            last_kick: 0,
+        }
+    }
+
+    /// Cycle of the last kick.
+    pub fn last_kick(&self) -> u64 {
+        self.last_kick
+    }
+
+    fn enabled(&self) -> bool {
+        self.ctrl & CTRL_ENABLE != 0
+    }
+
+    fn kick(&mut self) {
+        self.last_kick = self.cycle;
+    }
+
+    fn remaining(&self) -> u32 {
+        let elapsed = self.cycle - self.last_kick;
+        if elapsed >= self.timeout as u64 {
+            return 0;
+        }
+        self.timeout - elapsed as u32
+    }
+}
+
+impl Registers for Watchdog {
+    fn base(&self) -> mem::Addr {
+        self.base
+    }
+
+    fn size(&self) -> mem::Addr {
+        SIZE
+    }
+
+    fn read_reg(&self, reg: mem::Addr) -> u32 {
+        match reg {
+            REG_CTRL => self.ctrl,
+            REG_TIMEOUT => self.timeout,
+            REG_COUNT => self.remaining(),
+            REG_STATUS => self.status,
+            _ => 0,
+        }
+    }
+
+    fn write_reg(&mut self, reg: mem::Addr, val: u32) {
+        match reg {
+            REG_CTRL => {
+                if !self.enabled() && val & CTRL_ENABLE != 0 {
+                    debug!("WDT: enabled, timeout {} cycles", self.timeout);
+                    self.kick();
+                }
+                self.ctrl = val;
+            },
+            REG_TIMEOUT => self.timeout = val,
+            REG_KICK => self.kick(),
+            REG_STATUS => self.status &= !val,
+            _ => (),
+        }
+    }
+
+    fn write_reg_byte(&mut self, reg: mem::Addr, addr: mem::Addr, val: mem::Byte) {
+        // Writing a single byte must not clear other pending bits.
+        let old = if reg == REG_STATUS { 0 } else { self.read_reg(reg) };
+        self.write_reg(reg, reg_set_byte(old, addr, val));
+    }
+}
+registers!(Watchdog);
+
+impl BusDevice for Watchdog {
+    fn describe(&self) -> String {
+        format!("watchdog at {:#X}, {:?}", self.base, self.action)
+    }
+
+    fn tick(&mut self) -> Result<(), RError> {
+        self.cycle += 1;
+        if !self.enabled() || self.remaining() > 0 {
+            return Ok(());
+        }
+        debug!("WDT: expired at cycle {}, last kick at {}", self.cycle, self.last_kick);
+        // Expired, don't fire again until re-enabled.
+        self.ctrl &= !CTRL_ENABLE;
+        match self.action {
+            WatchdogAction::Reset => {
+                self.status |= STATUS_WDT_RESET;
+                bail!(RErrorKind::WatchdogReset(self.last_kick))
+            },
+            WatchdogAction::Halt => bail!(RErrorKind::WatchdogHalt(self.last_kick)),
+        }
+    }
+
+    fn reset(&mut self) {
+        self.ctrl = 0;
+        self.last_kick = self.cycle;
+    }
+}
+impl MemoryBusDevice for Watchdog {}
+impl MemoryBusDevice32be for Watchdog {}
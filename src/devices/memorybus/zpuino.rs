@@ -0,0 +1,416 @@
+//! I/O of ZPUino, as on the Papilio boards.
+//!
+//! I/O is split into 16 slots of `1 << SLOT_BITS` bytes from `IO_BASE`,
+//! registers are 32 bit big endian words, register `n` of a slot at `slot + n * 4`.
+//!
+//! - Slot 0, SPI: `0` SPICTL, bit 0 ready, bits 9 and 10 transfer size
+//!   of 8, 16, 24 or 32 bits. `1` SPIDATA, writing starts a transfer,
+//!   reading gets the bits shifted in. The SPI flash hangs off it,
+//!   selected by driving GPIO pin `FLASH_CS_PIN` low.
+//! - Slot 1, UART: `0` UARTDATA, `1` UARTCTL, bit 0 set if data is available,
+//!   bit 1 set while transmitting. Never, here. Talks to the terminal,
+//!   unless given another `SerialBackend`.
+//! - Slot 2, GPIO: `0` to `3` GPIODATA, 128 pins, `4` to `7` GPIOTRIS, 1 = input.
+//! - Slot 3, timers: timer 0 at register `0`, timer 1 at `64`, each with
+//!   `+0` CTL, `+1` CNT, `+2` CMP, `+3` OCR. See the `TCTL*` bits.
+//! - Slot 4, interrupts: `0` INTRCTL, writing bit 0 enables interrupts, reading
+//!   gets the pending lines. `1` INTRMASK, enabled lines.
+//!   Timer 0 is line `INTRLINE_TIMER0`, timer 1 `INTRLINE_TIMER1`.
+//!
+//! Unused slots and registers read 0 and ignore writes.
+
+extern crate mem;
+
+use self::mem::errors::*;
+use bus::memorybus::{MemoryBusDevice, MemoryBusDevice32be};
+use bus::BusDevice;
+use devices::serial::{SerialBackend, Stdio};
+use errors::Error as RError;
+
+use super::{reg_byte, reg_set_byte};
+
+use std::cell::RefCell;
+use std::mem::replace;
+
+pub const IO_BASE: mem::Addr = 0x08000000;
+pub const SLOT_BITS: usize = 23;
+const SLOTS: mem::Addr = 16;
+
+const SLOT_SPI: usize = 0;
+const SLOT_UART: usize = 1;
+const SLOT_GPIO: usize = 2;
+const SLOT_TIMERS: usize = 3;
+const SLOT_INTR: usize = 4;
+
+const SPICTL_READY: u32 = 1 << 0;
+const SPICTL_TS_SHIFT: u32 = 9;
+
+const UARTCTL_AVAIL: u32 = 1 << 0;
+
+/// Timer enable.
+pub const TCTLENA: u32 = 1 << 0;
+/// Clear on compare match.
+pub const TCTLCCM: u32 = 1 << 1;
+/// Count up.
+pub const TCTLDIR: u32 = 1 << 2;
+/// Interrupt enable.
+pub const TCTLIEN: u32 = 1 << 3;
+/// Prescaler, 3 bits: 1, 2, 4, 8, 16, 64, 256 or 1024.
+pub const TCTLCP_SHIFT: u32 = 4;
+/// Interrupt flag, write 0 to clear.
+pub const TCTLIF: u32 = 1 << 7;
+
+const PRESCALERS: [u64; 8] = [1, 2, 4, 8, 16, 64, 256, 1024];
+
+pub const INTRLINE_TIMER0: u32 = 3;
+pub const INTRLINE_TIMER1: u32 = 4;
+
+/// GPIO pin selecting the SPI flash, active low.
+pub const FLASH_CS_PIN: usize = 40;
+
+#[derive(Debug, Default, Clone)]
+struct Timer {
+    ctl: u32,
+    cnt: u32,
+    cmp: u32,
+    ocr: u32,
+    prescale: u64,
+}
+
+impl Timer {
+    fn read(&self, reg: usize) -> u32 {
+        match reg {
+            0 => self.ctl,
+            1 => self.cnt,
+            2 => self.cmp,
+            3 => self.ocr,
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, reg: usize, val: u32) {
+        match reg {
+            0 => {
+                // The flag can only be cleared.
+                let flag = self.ctl & val & TCTLIF;
+                self.ctl = (val & !TCTLIF) | flag;
+            },
+            1 => self.cnt = val,
+            2 => self.cmp = val,
+            3 => self.ocr = val,
+            _ => (),
+        }
+    }
+
+    fn tick(&mut self) {
+        if self.ctl & TCTLENA == 0 {
+            return;
+        }
+        self.prescale += 1;
+        if self.prescale < PRESCALERS[((self.ctl >> TCTLCP_SHIFT) & 7) as usize] {
+            return;
+        }
+        self.prescale = 0;
+        self.cnt = match self.ctl & TCTLDIR {
+            0 => self.cnt.wrapping_sub(1),
+            _ => self.cnt.wrapping_add(1),
+        };
+        if self.cnt == self.cmp {
+            self.ctl |= TCTLIF;
+            if self.ctl & TCTLCCM != 0 {
+                self.cnt = 0;
+            }
+        }
+    }
+
+    fn interrupt(&self) -> bool {
+        self.ctl & (TCTLIEN | TCTLIF) == TCTLIEN | TCTLIF
+    }
+}
+
+/// SPI flash, read only. Knows READ, FAST READ, RDSR and RDID.
+#[derive(Debug, Default, Clone)]
+struct Flash {
+    data: Vec<u8>,
+    /// Command and address bytes so far.
+    cmd: Vec<u8>,
+    addr: usize,
+}
+
+/// RDID answer of an M25P80.
+const FLASH_ID: [u8; 3] = [0x20, 0x20, 0x14];
+
+impl Flash {
+    fn deselect(&mut self) {
+        self.cmd.clear();
+    }
+
+    fn exchange(&mut self, byte: u8) -> u8 {
+        if self.cmd.is_empty() {
+            self.cmd.push(byte);
+            return 0xFF;
+        }
+        match self.cmd[0] {
+            // Three address bytes, FAST READ has a dummy byte after them.
+            op @ 0x03 | op @ 0x0B => {
+                let header = if op == 0x0B { 5 } else { 4 };
+                if self.cmd.len() < header {
+                    self.cmd.push(byte);
+                    if self.cmd.len() == 4 {
+                        self.addr = ((self.cmd[1] as usize) << 16) | ((self.cmd[2] as usize) << 8) | self.cmd[3] as usize;
+                    }
+                    return 0xFF;
+                }
+                let b = self.data.get(self.addr).cloned().unwrap_or(0xFF);
+                self.addr += 1;
+                b
+            },
+            // Never busy.
+            0x05 => 0x00,
+            0x9F => {
+                let n = self.cmd.len() - 1;
+                if n < FLASH_ID.len() {
+                    self.cmd.push(byte);
+                }
+                FLASH_ID.get(n).cloned().unwrap_or(0xFF)
+            },
+            _ => 0xFF,
+        }
+    }
+}
+
+pub struct ZPUinoIO {
+    spi_ctl: u32,
+    spi_data: u32,
+    flash: Flash,
+
+    uart_ctl: u32,
+    serial: RefCell<Box<SerialBackend>>,
+
+    gpio_out: [u32; 4],
+    gpio_in: [u32; 4],
+    gpio_tris: [u32; 4],
+
+    timers: [Timer; 2],
+
+    intr_ctl: u32,
+    intr_mask: u32,
+}
+
+impl ZPUinoIO {
+    /// ZPUino I/O, with `flash` as contents of the SPI flash.
+    pub fn new(flash: Vec<u8>) -> ZPUinoIO {
+        ZPUinoIO {
+            spi_ctl: 0,
+            spi_data: 0,
+            flash: Flash { data: flash, cmd: vec![], addr: 0 },
+
+            uart_ctl: 0,
+            serial: RefCell::new(Box::new(Stdio)),
+
+            gpio_out: [0; 4],
+            gpio_in: [0; 4],
+            gpio_tris: [!0; 4],
+
+            timers: [Timer::default(), Timer::default()],
+
+            intr_ctl: 0,
+            intr_mask: 0,
+        }
+    }
+
+    /// Use `serial` instead of the terminal.
+    pub fn with_serial(mut self, serial: Box<SerialBackend>) -> ZPUinoIO {
+        self.serial = RefCell::new(serial);
+        self
+    }
+
+    /// Drive input pin `pin`.
+    pub fn set_pin(&mut self, pin: usize, level: bool) {
+        let bit = 1 << (pin % 32);
+        if level {
+            self.gpio_in[pin / 32] |= bit;
+        } else {
+            self.gpio_in[pin / 32] &= !bit;
+        }
+    }
+
+    /// Level of pin `pin`, as driven by the guest for outputs.
+    pub fn pin(&self, pin: usize) -> bool {
+        self.gpio(pin / 32) & (1 << (pin % 32)) != 0
+    }
+
+    fn gpio(&self, n: usize) -> u32 {
+        (self.gpio_out[n] & !self.gpio_tris[n]) | (self.gpio_in[n] & self.gpio_tris[n])
+    }
+
+    /// Pending interrupt lines.
+    fn pending(&self) -> u32 {
+        let mut lines = 0;
+        if self.timers[0].interrupt() {
+            lines |= 1 << INTRLINE_TIMER0;
+        }
+        if self.timers[1].interrupt() {
+            lines |= 1 << INTRLINE_TIMER1;
+        }
+        lines
+    }
+
+    fn flash_selected(&self) -> bool {
+        !self.pin(FLASH_CS_PIN)
+    }
+
+    fn spi_transfer(&mut self, val: u32) {
+        let bytes = ((self.spi_ctl >> SPICTL_TS_SHIFT) & 3) + 1;
+        let mut data = 0;
+        for i in (0..bytes).rev() {
+            let out = (val >> (i * 8)) as u8;
+            let inb = if self.flash_selected() { self.flash.exchange(out) } else { 0xFF };
+            data = (data << 8) | inb as u32;
+        }
+        self.spi_data = data;
+    }
+
+    /// Is input waiting? Backends failing to tell have none.
+    fn uart_avail(&self) -> bool {
+        self.serial.borrow_mut().poll().unwrap_or(false)
+    }
+
+    /// Next input byte, 0 without one, never blocking.
+    fn uart_read(&self) -> u32 {
+        if !self.uart_avail() {
+            return 0;
+        }
+        match self.serial.borrow_mut().read() {
+            Ok(Some(c)) => c as u32,
+            _ => 0,
+        }
+    }
+
+    fn decode(&self, addr: mem::Addr) -> Result<(usize, usize), Error> {
+        if addr < IO_BASE {
+            bail!(ErrorKind::TooSmall(addr, IO_BASE));
+        }
+        if addr >= IO_BASE + (SLOTS << SLOT_BITS) {
+            bail!(ErrorKind::TooBig(addr, IO_BASE + (SLOTS << SLOT_BITS) - 1));
+        }
+        let off = addr - IO_BASE;
+        Ok((off >> SLOT_BITS, (off & ((1 << SLOT_BITS) - 1)) >> 2))
+    }
+
+    fn read_reg(&self, slot: usize, reg: usize) -> u32 {
+        match (slot, reg) {
+            (SLOT_SPI, 0) => self.spi_ctl | SPICTL_READY,
+            (SLOT_SPI, 1) => self.spi_data,
+            (SLOT_UART, 0) => self.uart_read(),
+            (SLOT_UART, 1) => if self.uart_avail() { UARTCTL_AVAIL } else { 0 },
+            (SLOT_GPIO, n) if n < 4 => self.gpio(n),
+            (SLOT_GPIO, n) if n < 8 => self.gpio_tris[n - 4],
+            (SLOT_TIMERS, n) if n < 4 => self.timers[0].read(n),
+            (SLOT_TIMERS, n) if n >= 64 && n < 68 => self.timers[1].read(n - 64),
+            (SLOT_INTR, 0) => self.pending(),
+            (SLOT_INTR, 1) => self.intr_mask,
+            _ => 0,
+        }
+    }
+
+    fn write_reg(&mut self, slot: usize, reg: usize, val: u32) -> Result<(), Error> {
+        match (slot, reg) {
+            (SLOT_SPI, 0) => self.spi_ctl = val & !SPICTL_READY,
+            (SLOT_SPI, 1) => self.spi_transfer(val),
+            (SLOT_UART, 0) => {
+                debug!("ZPUINO: UART: {}", val as u8 as char);
+                if self.serial.borrow_mut().write(val as u8).is_err() {
+                    bail!(ErrorKind::HardwareFault(IO_BASE + (SLOT_UART << SLOT_BITS), "UART failed to write to stdout."));
+                }
+            },
+            (SLOT_UART, 1) => self.uart_ctl = val,
+            (SLOT_GPIO, n) if n < 4 => self.write_gpio(n, val, self.gpio_tris[n]),
+            (SLOT_GPIO, n) if n < 8 => {
+                let out = self.gpio_out[n - 4];
+                self.write_gpio(n - 4, out, val)
+            },
+            (SLOT_TIMERS, n) if n < 4 => self.timers[0].write(n, val),
+            (SLOT_TIMERS, n) if n >= 64 && n < 68 => self.timers[1].write(n - 64, val),
+            (SLOT_INTR, 0) => self.intr_ctl = val,
+            (SLOT_INTR, 1) => self.intr_mask = val,
+            _ => (),
+        }
+        Ok(())
+    }
+
+    /// Set outputs and directions of GPIO word `n`, deselecting the flash on a rising CS.
+    fn write_gpio(&mut self, n: usize, out: u32, tris: u32) {
+        let selected = self.flash_selected();
+        self.gpio_out[n] = out;
+        self.gpio_tris[n] = tris;
+        if selected && !self.flash_selected() {
+            self.flash.deselect();
+        }
+    }
+}
+
+impl mem::MemoryBlock for ZPUinoIO {
+    fn get_size(&self) -> usize {
+        IO_BASE + (SLOTS << SLOT_BITS) - 1 // highest address.
+    }
+
+    fn set(&mut self, addr: mem::Addr, val: mem::Byte) -> Result<(), Error> {
+        let (slot, reg) = self.decode(addr)?;
+        // Data registers take the byte as is, no read-modify-write with side effects.
+        let new = match (slot, reg) {
+            (SLOT_SPI, 1) | (SLOT_UART, 0) => val as u32,
+            _ => reg_set_byte(self.read_reg(slot, reg), addr, val),
+        };
+        self.write_reg(slot, reg, new)
+    }
+
+    fn get(&self, addr: mem::Addr) -> Result<mem::Byte, Error> {
+        let (slot, reg) = self.decode(addr)?;
+        Ok(reg_byte(self.read_reg(slot, reg), addr))
+    }
+}
+
+impl mem::MemoryBlock32be for ZPUinoIO {
+    fn set32be(&mut self, addr: mem::Addr, val: u32) -> Result<(), Error> {
+        if addr & 3 != 0 {
+            bail!(ErrorKind::InvalidAddr(addr));
+        }
+        let (slot, reg) = self.decode(addr)?;
+        self.write_reg(slot, reg, val)
+    }
+
+    fn get32be(&self, addr: mem::Addr) -> Result<u32, Error> {
+        if addr & 3 != 0 {
+            bail!(ErrorKind::InvalidAddr(addr));
+        }
+        let (slot, reg) = self.decode(addr)?;
+        Ok(self.read_reg(slot, reg))
+    }
+}
+
+impl BusDevice for ZPUinoIO {
+    fn describe(&self) -> String {
+        format!("ZPUino I/O at {:#X}", IO_BASE)
+    }
+
+    fn tick(&mut self) -> Result<(), RError> {
+        for t in self.timers.iter_mut() {
+            t.tick();
+        }
+        Ok(())
+    }
+
+    fn reset(&mut self) {
+        let flash = replace(&mut self.flash.data, vec![]);
+        let serial = replace(&mut self.serial, RefCell::new(Box::new(Stdio)));
+        *self = ZPUinoIO::new(flash);
+        self.serial = serial;
+    }
+
+    fn interrupt(&self) -> bool {
+        self.intr_ctl & 1 != 0 && self.pending() & self.intr_mask != 0
+    }
+}
+impl MemoryBusDevice for ZPUinoIO {}
+impl MemoryBusDevice32be for ZPUinoIO {}
@@ -1,3 +1,5 @@
 // Stub.
 pub mod memorybus;
-
+pub mod i2c;
+pub mod net;
+pub mod serial;
@@ -0,0 +1,54 @@
+//! Network backends.
+//!
+//! Host side of emulated network interfaces, moving whole Ethernet frames.
+
+pub mod pcap;
+pub mod udp;
+
+use std::io;
+use std::collections::VecDeque;
+
+pub trait EthBackend {
+    /// Send a frame. `cycle` is the cycle it left the interface.
+    fn send(&mut self, cycle: u64, frame: &[u8]) -> io::Result<()>;
+
+    /// Receive a frame, if one is waiting.
+    fn recv(&mut self) -> io::Result<Option<Vec<u8>>>;
+}
+
+/// Sends every frame right back.
+pub struct Loopback {
+    queue: VecDeque<Vec<u8>>,
+}
+
+impl Loopback {
+    pub fn new() -> Loopback {
+        Loopback {
+            queue: VecDeque::new(),
+        }
+    }
+}
+
+impl EthBackend for Loopback {
+    fn send(&mut self, _cycle: u64, frame: &[u8]) -> io::Result<()> {
+        self.queue.push_back(frame.to_vec());
+        Ok(())
+    }
+
+    fn recv(&mut self) -> io::Result<Option<Vec<u8>>> {
+        Ok(self.queue.pop_front())
+    }
+}
+
+/// Goes nowhere, receives nothing.
+pub struct Unplugged;
+
+impl EthBackend for Unplugged {
+    fn send(&mut self, _cycle: u64, _frame: &[u8]) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn recv(&mut self) -> io::Result<Option<Vec<u8>>> {
+        Ok(None)
+    }
+}
@@ -0,0 +1,108 @@
+//! pcap file backend.
+//!
+//! Sent frames get written to a pcap file, received ones are read from another.
+//! Timestamps of written frames are the cycle they were sent at, as microseconds.
+
+use super::EthBackend;
+
+use std::io;
+use std::io::prelude::*;
+use std::io::{BufReader, BufWriter};
+use std::fs::File;
+use std::path::Path;
+
+const MAGIC: u32 = 0xa1b2c3d4;
+const LINKTYPE_ETHERNET: u32 = 1;
+const SNAPLEN: u32 = 65535;
+
+pub struct Pcap {
+    reader: Option<(BufReader<File>, bool)>,
+    writer: Option<BufWriter<File>>,
+}
+
+impl Pcap {
+    /// Read frames from `input` and write frames to `output`, both optional.
+    pub fn new<P: AsRef<Path>>(input: Option<P>, output: Option<P>) -> io::Result<Pcap> {
+        let reader = match input {
+            Some(path) => {
+                let mut r = BufReader::new(File::open(path)?);
+                let mut hdr = [0u8; 24];
+                r.read_exact(&mut hdr)?;
+                let swapped = match (u32_le(&hdr[0..4]), u32_be(&hdr[0..4])) {
+                    (MAGIC, _) => false,
+                    (_, MAGIC) => true,
+                    _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "not a pcap file")),
+                };
+                Some((r, swapped))
+            },
+            None => None,
+        };
+        let writer = match output {
+            Some(path) => {
+                let mut w = BufWriter::new(File::create(path)?);
+                let mut hdr = vec![];
+                put_u32_le(&mut hdr, MAGIC);
+                hdr.extend_from_slice(&[2, 0, 4, 0]); // version 2.4
+                put_u32_le(&mut hdr, 0); // thiszone
+                put_u32_le(&mut hdr, 0); // sigfigs
+                put_u32_le(&mut hdr, SNAPLEN);
+                put_u32_le(&mut hdr, LINKTYPE_ETHERNET);
+                w.write_all(&hdr)?;
+                Some(w)
+            },
+            None => None,
+        };
+        Ok(Pcap {
+            reader: reader,
+            writer: writer,
+        })
+    }
+}
+
+impl EthBackend for Pcap {
+    fn send(&mut self, cycle: u64, frame: &[u8]) -> io::Result<()> {
+        if let Some(ref mut w) = self.writer {
+            let mut rec = vec![];
+            put_u32_le(&mut rec, (cycle / 1000000) as u32);
+            put_u32_le(&mut rec, (cycle % 1000000) as u32);
+            put_u32_le(&mut rec, frame.len() as u32);
+            put_u32_le(&mut rec, frame.len() as u32);
+            w.write_all(&rec)?;
+            w.write_all(frame)?;
+            w.flush()?;
+        }
+        Ok(())
+    }
+
+    fn recv(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let (r, swapped) = match self.reader {
+            Some((ref mut r, swapped)) => (r, swapped),
+            None => return Ok(None),
+        };
+        let mut rec = [0u8; 16];
+        match r.read_exact(&mut rec) {
+            Ok(()) => (),
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        let len = if swapped { u32_be(&rec[8..12]) } else { u32_le(&rec[8..12]) };
+        if len > SNAPLEN {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "pcap record longer than the snaplen"));
+        }
+        let mut frame = vec![0; len as usize];
+        r.read_exact(&mut frame)?;
+        Ok(Some(frame))
+    }
+}
+
+fn u32_le(b: &[u8]) -> u32 {
+    (b[0] as u32) | ((b[1] as u32) << 8) | ((b[2] as u32) << 16) | ((b[3] as u32) << 24)
+}
+
+fn u32_be(b: &[u8]) -> u32 {
+    ((b[0] as u32) << 24) | ((b[1] as u32) << 16) | ((b[2] as u32) << 8) | (b[3] as u32)
+}
+
+fn put_u32_le(out: &mut Vec<u8>, v: u32) {
+    out.extend_from_slice(&[v as u8, (v >> 8) as u8, (v >> 16) as u8, (v >> 24) as u8]);
+}
@@ -0,0 +1,48 @@
+//! UDP socket backend.
+//!
+//! Connects two rose instances, each frame is sent as one datagram.
+//! Point them at each other: `bind` of one is `peer` of the other.
+
+use super::EthBackend;
+
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+
+pub struct Udp {
+    sock: UdpSocket,
+}
+
+impl Udp {
+    pub fn new(bind: SocketAddr, peer: SocketAddr) -> io::Result<Udp> {
+        let sock = UdpSocket::bind(bind)?;
+        sock.connect(peer)?;
+        sock.set_nonblocking(true)?;
+        Ok(Udp {
+            sock: sock,
+        })
+    }
+}
+
+impl EthBackend for Udp {
+    fn send(&mut self, _cycle: u64, frame: &[u8]) -> io::Result<()> {
+        match self.sock.send(frame) {
+            Ok(_) => Ok(()),
+            // Nobody listening on the other end, like an unplugged cable.
+            Err(ref e) if e.kind() == io::ErrorKind::ConnectionRefused => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn recv(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let mut buf = vec![0; 65536];
+        match self.sock.recv(&mut buf) {
+            Ok(len) => {
+                buf.truncate(len);
+                Ok(Some(buf))
+            },
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(None),
+            Err(ref e) if e.kind() == io::ErrorKind::ConnectionRefused => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
@@ -0,0 +1,194 @@
+//! Serial backends.
+//!
+//! Host side of emulated UARTs, moving single bytes.
+
+use std::io;
+use std::io::prelude::*;
+use std::cell::RefCell;
+use std::cmp;
+use std::collections::VecDeque;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+pub trait SerialBackend {
+    /// Send a byte from the guest.
+    fn write(&mut self, byte: u8) -> io::Result<()>;
+
+    /// Receive a byte for the guest, if one is waiting.
+    fn read(&mut self) -> io::Result<Option<u8>>;
+
+    /// Is a byte waiting? Never blocks.
+    /// Backends that can't tell say yes, reading then blocks until one arrives.
+    fn poll(&mut self) -> io::Result<bool> {
+        Ok(true)
+    }
+}
+
+/// The terminal: stdout and stdin. Reads block until a byte arrives.
+pub struct Stdio;
+
+impl SerialBackend for Stdio {
+    fn write(&mut self, byte: u8) -> io::Result<()> {
+        io::stdout().write_all(&[byte])
+    }
+
+    fn read(&mut self) -> io::Result<Option<u8>> {
+        let mut buf = [0; 1];
+        match io::stdin().read(&mut buf)? {
+            0 => Ok(None),
+            _ => Ok(Some(buf[0])),
+        }
+    }
+}
+
+struct Input {
+    bytes: Receiver<u8>,
+    /// Byte `poll` took off the channel.
+    next: Option<u8>,
+}
+
+/// The terminal, with stdin read on a thread of its own, so `poll` doesn't block.
+/// Reads block until a byte arrives, like with `Stdio`.
+/// Clones share the input.
+#[derive(Clone)]
+pub struct Terminal {
+    input: Rc<RefCell<Input>>,
+}
+
+impl Terminal {
+    /// Start reading stdin.
+    pub fn new() -> Terminal {
+        Terminal::start(None)
+    }
+
+    /// Start reading stdin, taking `byte` out of the input and raising `flag` for it,
+    /// like `Machine::pause_handle`. Noticed right away, whether the guest reads or not.
+    pub fn with_escape(byte: u8, flag: Arc<AtomicBool>) -> Terminal {
+        Terminal::start(Some((byte, flag)))
+    }
+
+    fn start(escape: Option<(u8, Arc<AtomicBool>)>) -> Terminal {
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let stdin = io::stdin();
+            for byte in stdin.lock().bytes() {
+                match (byte, &escape) {
+                    (Ok(b), &Some((esc, ref flag))) if b == esc => flag.store(true, Ordering::SeqCst),
+                    (Ok(b), _) => if tx.send(b).is_err() { break },
+                    (Err(_), _) => break,
+                }
+            }
+        });
+        Terminal {
+            input: Rc::new(RefCell::new(Input {
+                bytes: rx,
+                next: None,
+            })),
+        }
+    }
+
+    /// Next input byte, waiting for it. None at the end of stdin.
+    fn next(&self) -> Option<u8> {
+        let mut input = self.input.borrow_mut();
+        match input.next.take() {
+            Some(b) => Some(b),
+            None => input.bytes.recv().ok(),
+        }
+    }
+}
+
+impl SerialBackend for Terminal {
+    fn write(&mut self, byte: u8) -> io::Result<()> {
+        Stdio.write(byte)
+    }
+
+    fn read(&mut self) -> io::Result<Option<u8>> {
+        Ok(self.next())
+    }
+
+    fn poll(&mut self) -> io::Result<bool> {
+        let mut input = self.input.borrow_mut();
+        if input.next.is_none() {
+            input.next = input.bytes.try_recv().ok();
+        }
+        Ok(input.next.is_some())
+    }
+}
+
+/// Reading input for the host, say a monitor sharing the terminal with the guest.
+/// Reads a byte at a time, so nothing meant for the guest gets buffered away.
+impl Read for Terminal {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        match self.next() {
+            Some(b) => {
+                buf[0] = b;
+                Ok(1)
+            },
+            None => Ok(0),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Buffers {
+    output: Vec<u8>,
+    input: VecDeque<u8>,
+}
+
+/// Collects the output and hands out queued input, for running guests headless.
+/// Clones share the buffers, keep one to look at them.
+#[derive(Clone, Default)]
+pub struct Buffer {
+    inner: Rc<RefCell<Buffers>>,
+}
+
+impl Buffer {
+    pub fn new() -> Buffer {
+        Buffer::default()
+    }
+
+    /// Everything the guest sent so far.
+    pub fn output(&self) -> Vec<u8> {
+        self.inner.borrow().output.clone()
+    }
+
+    /// What the guest sent from byte `from` on.
+    pub fn output_from(&self, from: usize) -> Vec<u8> {
+        let output = &self.inner.borrow().output;
+        output[cmp::min(from, output.len())..].to_vec()
+    }
+
+    /// Number of bytes the guest sent so far.
+    pub fn output_len(&self) -> usize {
+        self.inner.borrow().output.len()
+    }
+
+    /// Queue input for the guest.
+    pub fn send(&self, data: &[u8]) {
+        self.inner.borrow_mut().input.extend(data);
+    }
+}
+
+impl SerialBackend for Buffer {
+    fn write(&mut self, byte: u8) -> io::Result<()> {
+        self.inner.borrow_mut().output.push(byte);
+        Ok(())
+    }
+
+    fn read(&mut self) -> io::Result<Option<u8>> {
+        Ok(self.inner.borrow_mut().input.pop_front())
+    }
+
+    fn poll(&mut self) -> io::Result<bool> {
+        Ok(!self.inner.borrow().input.is_empty())
+    }
+}
+
+/// Input byte for getting out of the guest's console: Ctrl-A.
+pub const ESCAPE: u8 = 0x01;
@@ -12,9 +12,29 @@ error_chain! {
         Mem(mem::errors::Error, mem::errors::ErrorKind);
     }
 
+    foreign_links {
+        Io(::std::io::Error);
+    }
+
     errors {
         CPUNotRunning {
             description("cpu's state is not running")
         }
+        WatchdogReset(last_kick: u64) {
+            description("watchdog expired, machine needs a reset")
+            display("watchdog expired, last kicked at cycle {}", last_kick)
+        }
+        WatchdogHalt(last_kick: u64) {
+            description("watchdog expired, machine halted")
+            display("watchdog expired, last kicked at cycle {}", last_kick)
+        }
+        Exit(code: u32) {
+            description("guest exited")
+            display("guest exited with code {}", code)
+        }
+        ExpectTimeout(pattern: String, cycles: u64) {
+            description("timed out waiting for console output")
+            display("no output matching {:?} within {} cycles", pattern, cycles)
+        }
     }
 }
@@ -0,0 +1,219 @@
+//! Expect-style serial automation.
+//!
+//! Drives interactive guests through a `serial::Buffer` console: wait for the
+//! output to match a regex, then send input, like expect(1) does.
+//! Timeouts count emulated cycles, so scripts behave the same on any host.
+//!
+//! Scripts have one command per line, empty lines and lines starting with `#` are skipped.
+//! Trailing whitespace is dropped, write `\x20` where it matters:
+//!
+//! ```text
+//! timeout 5000000      # cycles to wait in the following expects
+//! expect login:
+//! send root\n          # \n, \r, \t, \\ and \xNN escapes
+//! expect [#$]\x20$
+//! ```
+
+extern crate regex;
+
+use self::regex::bytes::Regex;
+
+use errors::*;
+use machine::{Machine, StopReason};
+use devices::serial::Buffer;
+
+use std::cmp;
+use std::fs::File;
+use std::io::Read;
+use std::time::Instant;
+
+/// Cycles to wait for a match, unless set otherwise.
+pub const DEFAULT_TIMEOUT: u64 = 10000000;
+
+/// Cycles run between looks at the wall clock.
+const CHUNK: u64 = 10000;
+
+pub struct Expect<'a> {
+    machine: &'a mut Machine,
+    console: Buffer,
+    /// Output up to here was consumed by earlier matches.
+    seen: usize,
+    timeout: u64,
+    /// Cycle count and wall clock time to give up at, whatever the timeout.
+    limit: Option<u64>,
+    deadline: Option<Instant>,
+}
+
+impl<'a> Expect<'a> {
+    /// Automate `machine`, which has `console` on its UART.
+    pub fn new(machine: &'a mut Machine, console: Buffer) -> Expect<'a> {
+        Expect {
+            seen: console.output_len(),
+            machine: machine,
+            console: console,
+            timeout: DEFAULT_TIMEOUT,
+            limit: None,
+            deadline: None,
+        }
+    }
+
+    pub fn machine(&mut self) -> &mut Machine {
+        self.machine
+    }
+
+    /// Cycles to wait for a match.
+    pub fn set_timeout(&mut self, cycles: u64) {
+        self.timeout = cycles;
+    }
+
+    /// Give up once the machine ran `cycles` in total.
+    pub fn set_limit(&mut self, cycles: Option<u64>) {
+        self.limit = cycles;
+    }
+
+    /// Give up at `deadline`, checked every few thousand cycles.
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
+    }
+
+    /// Run until the output not matched before matches `pattern`, returning the match.
+    pub fn expect(&mut self, pattern: &str) -> Result<Vec<u8>, Error> {
+        let re = Regex::new(pattern).chain_err(|| format!("invalid pattern: {}", pattern))?;
+        self.expect_re(&re)
+    }
+
+    fn expect_re(&mut self, re: &Regex) -> Result<Vec<u8>, Error> {
+        let deadline = self.machine.cycles().saturating_add(self.timeout);
+        loop {
+            let output = self.console.output_from(self.seen);
+            if let Some(m) = re.find(&output) {
+                self.seen += m.end();
+                return Ok(m.as_bytes().to_vec());
+            }
+            if self.machine.cycles() >= deadline {
+                bail!(ErrorKind::ExpectTimeout(re.as_str().to_string(), self.timeout));
+            }
+            if self.limit.map_or(false, |l| self.machine.cycles() >= l) {
+                bail!("instruction limit reached waiting for {:?}", re.as_str());
+            }
+            if self.deadline.map_or(false, |d| Instant::now() >= d) {
+                bail!("timed out waiting for {:?}", re.as_str());
+            }
+            let mut until = cmp::min(deadline, self.limit.unwrap_or(u64::max_value()));
+            if self.deadline.is_some() {
+                until = cmp::min(until, self.machine.cycles().saturating_add(CHUNK));
+            }
+
+            // Look again once something got printed.
+            let len = self.console.output_len();
+            let console = self.console.clone();
+            match self.machine.run_until(|m| m.cycles() >= until || console.output_len() != len) {
+                StopReason::Condition => (),
+                StopReason::Fault(e) => return Err(e).chain_err(|| format!("waiting for {:?}", re.as_str())),
+                StopReason::Exit(code) => bail!(ErrorKind::Exit(code)),
+                StopReason::Halted => bail!(ErrorKind::CPUNotRunning),
+                reason => bail!("stopped waiting for {:?}: {:?}", re.as_str(), reason),
+            }
+        }
+    }
+
+    /// Queue `data` as input for the guest.
+    pub fn send(&mut self, data: &[u8]) {
+        self.console.send(data);
+    }
+}
+
+enum Command {
+    Expect(Regex),
+    Send(Vec<u8>),
+    Timeout(u64),
+}
+
+/// A parsed script, with line numbers for errors.
+pub struct Script {
+    commands: Vec<(usize, Command)>,
+}
+
+impl Script {
+    pub fn parse(s: &str) -> Result<Script, Error> {
+        let mut commands = vec![];
+        for (i, line) in s.lines().enumerate() {
+            let n = i + 1;
+            let line = line.trim_start();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (cmd, arg) = match line.find(char::is_whitespace) {
+                Some(at) => (&line[..at], line[at..].trim_start()),
+                None => (line, ""),
+            };
+            let arg = strip_comment(arg);
+            let command = match cmd {
+                "expect" => Command::Expect(Regex::new(arg).chain_err(|| format!("line {}: invalid pattern", n))?),
+                "send" => Command::Send(unescape(arg).chain_err(|| format!("line {}: invalid escape", n))?),
+                "timeout" => Command::Timeout(arg.parse().chain_err(|| format!("line {}: invalid timeout: {}", n, arg))?),
+                _ => bail!("line {}: unknown command: {}", n, cmd),
+            };
+            commands.push((n, command));
+        }
+        Ok(Script { commands: commands })
+    }
+
+    pub fn load(path: &str) -> Result<Script, Error> {
+        let mut s = String::new();
+        File::open(path).chain_err(|| format!("failed to open {}", path))?.read_to_string(&mut s)?;
+        Script::parse(&s).chain_err(|| format!("invalid script: {}", path))
+    }
+
+    pub fn run(&self, e: &mut Expect) -> Result<(), Error> {
+        for &(n, ref command) in &self.commands {
+            match *command {
+                Command::Expect(ref re) => {
+                    e.expect_re(re).chain_err(|| format!("script line {}", n))?;
+                },
+                Command::Send(ref data) => e.send(data),
+                Command::Timeout(cycles) => e.set_timeout(cycles),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Cut off a trailing comment, which needs whitespace before the `#`.
+fn strip_comment(s: &str) -> &str {
+    let mut prev = ' ';
+    for (i, c) in s.char_indices() {
+        if c == '#' && prev.is_whitespace() && i > 0 {
+            return s[..i].trim_end();
+        }
+        prev = c;
+    }
+    s.trim_end()
+}
+
+/// Turn the escapes scripts know into bytes.
+pub fn unescape(s: &str) -> Result<Vec<u8>, Error> {
+    let mut out = vec![];
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buf = [0; 4];
+            out.extend(c.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push(b'\n'),
+            Some('r') => out.push(b'\r'),
+            Some('t') => out.push(b'\t'),
+            Some('\\') => out.push(b'\\'),
+            Some('#') => out.push(b'#'),
+            Some('x') => {
+                let hex: String = chars.by_ref().take(2).collect();
+                out.push(u8::from_str_radix(&hex, 16).chain_err(|| format!("invalid hex escape: \\x{}", hex))?);
+            },
+            Some(c) => bail!("unknown escape: \\{}", c),
+            None => bail!("trailing backslash"),
+        }
+    }
+    Ok(out)
+}
@@ -6,6 +6,8 @@
 
 #[macro_use]
 extern crate error_chain;
+#[macro_use]
+extern crate serde_derive;
 
 pub mod errors;
 
@@ -14,5 +16,13 @@ pub mod errors;
 mod macros;
 
 pub mod bus;
+pub mod clock;
+pub mod config;
 pub mod cpu;
+pub mod debugger;
 pub mod devices;
+pub mod expect;
+pub mod machine;
+pub mod monitor;
+pub mod num;
+pub mod platforms;
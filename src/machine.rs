@@ -0,0 +1,179 @@
+//! Machines.
+//!
+//! A `Machine` owns a CPU, with its bus, and runs it, so frontends don't
+//! have to write their own run loop. Runs end with a `StopReason`.
+
+use errors::*;
+use cpu::{CPU, CPUState};
+use bus::watch::{Hit, Watchpoints};
+
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Why a run ended.
+#[derive(Debug)]
+pub enum StopReason {
+    /// The CPU isn't running anymore.
+    Halted,
+    /// About to run the instruction at a breakpoint.
+    Breakpoint(u32),
+    /// A watchpoint fired, on the instruction just run.
+    Watchpoint(Hit),
+    /// A step failed. Includes watchdog resets, reset the machine to go on.
+    Fault(Error),
+    /// The guest exited, with an exit code.
+    Exit(u32),
+    /// Ran the number of cycles asked for.
+    Limit,
+    /// The condition of `run_until` was met.
+    Condition,
+    /// Paused by `pause` or its handle.
+    Paused,
+}
+
+pub struct Machine {
+    cpu: Box<CPU>,
+    cycles: u64,
+    breakpoints: HashSet<u32>,
+    /// Breakpoint stopped at, not to stop there again right away.
+    at_breakpoint: Option<u32>,
+    paused: Arc<AtomicBool>,
+    watch: Option<Watchpoints>,
+}
+
+impl Machine {
+    pub fn new(cpu: Box<CPU>) -> Machine {
+        Machine {
+            cpu: cpu,
+            cycles: 0,
+            breakpoints: HashSet::new(),
+            at_breakpoint: None,
+            paused: Arc::new(AtomicBool::new(false)),
+            watch: None,
+        }
+    }
+
+    pub fn cpu(&self) -> &CPU {
+        &*self.cpu
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut CPU {
+        &mut *self.cpu
+    }
+
+    /// Cycles run so far.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn start(&mut self) -> Result<(), Error> {
+        self.cpu.start()
+    }
+
+    /// Reset the CPU and the devices on its bus.
+    pub fn reset(&mut self) -> Result<(), Error> {
+        self.at_breakpoint = None;
+        self.cpu.reset()
+    }
+
+    pub fn add_breakpoint(&mut self, pc: u32) {
+        self.breakpoints.insert(pc);
+    }
+
+    pub fn remove_breakpoint(&mut self, pc: u32) -> bool {
+        self.breakpoints.remove(&pc)
+    }
+
+    pub fn breakpoints(&self) -> &HashSet<u32> {
+        &self.breakpoints
+    }
+
+    /// Pause on `paused` instead, for sharing it with devices built before the machine.
+    pub fn with_pause_handle(mut self, paused: Arc<AtomicBool>) -> Machine {
+        self.paused = paused;
+        self
+    }
+
+    /// Stop with `Watchpoint` after instructions hitting one of `watch`,
+    /// the set handed to the `Watch` layers on the bus.
+    pub fn with_watchpoints(mut self, watch: Watchpoints) -> Machine {
+        self.watch = Some(watch);
+        self
+    }
+
+    /// Make the current or next run stop with `Paused`.
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::SeqCst);
+    }
+
+    /// Let runs go on again.
+    pub fn resume(&self) {
+        self.paused.store(false, Ordering::SeqCst);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    /// Flag behind `pause` and `resume`, for pausing from other threads or signal handlers.
+    pub fn pause_handle(&self) -> Arc<AtomicBool> {
+        self.paused.clone()
+    }
+
+    /// Run a single instruction, ignoring breakpoints and pauses.
+    pub fn step(&mut self) -> Option<StopReason> {
+        if self.cpu.state() != CPUState::Running {
+            return Some(StopReason::Halted);
+        }
+        self.at_breakpoint = None;
+        self.cycles += 1;
+        if let Err(e) = self.cpu.step() {
+            match *e.kind() {
+                ErrorKind::Exit(code) => return Some(StopReason::Exit(code)),
+                _ => (),
+            }
+            return Some(StopReason::Fault(e));
+        }
+        self.watch.as_ref().and_then(|w| w.take_hit()).map(StopReason::Watchpoint)
+    }
+
+    /// Run until stopped.
+    pub fn run(&mut self) -> StopReason {
+        self.run_inner(None, |_| false)
+    }
+
+    /// Run at most `cycles` cycles.
+    pub fn run_for(&mut self, cycles: u64) -> StopReason {
+        self.run_inner(Some(cycles), |_| false)
+    }
+
+    /// Run until `cond` holds, checking it before every instruction.
+    pub fn run_until<F: FnMut(&Machine) -> bool>(&mut self, cond: F) -> StopReason {
+        self.run_inner(None, cond)
+    }
+
+    fn run_inner<F: FnMut(&Machine) -> bool>(&mut self, limit: Option<u64>, mut cond: F) -> StopReason {
+        let mut ran = 0;
+        loop {
+            if self.is_paused() {
+                return StopReason::Paused;
+            }
+            if cond(self) {
+                return StopReason::Condition;
+            }
+            if limit.map_or(false, |l| ran >= l) {
+                return StopReason::Limit;
+            }
+            let pc = self.cpu.pc();
+            if self.at_breakpoint != Some(pc) && self.breakpoints.contains(&pc) {
+                self.at_breakpoint = Some(pc);
+                return StopReason::Breakpoint(pc);
+            }
+            if let Some(reason) = self.step() {
+                return reason;
+            }
+            ran += 1;
+        }
+    }
+}
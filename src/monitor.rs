@@ -0,0 +1,361 @@
+//! Machine monitor.
+//!
+//! Commands for looking at a stopped machine and poking it: stepping,
+//! registers, memory, breakpoints, watchpoints, disassembly and devices.
+//! Frontends hand it command lines, `repl` reads them from a reader.
+//!
+//! Watchpoints need a `bus::watch` layer on the machine's bus, with its
+//! `Watchpoints` handed to the monitor and the machine. They only fire while the monitor runs
+//! the machine, not when it looks at memory itself.
+//!
+//! Looking at memory peeks, so it doesn't disturb the machine: device
+//! registers, whose reads might do things, show up as `??`.
+
+use errors::*;
+use machine::{Machine, StopReason};
+use cpu::{CPU, CPUState};
+use bus::memorybus::MemoryBusDevice32be;
+use bus::watch::{Watchpoints, Watchpoint, Access, WatchAction};
+use num::parse_num;
+
+use std::io::{BufRead, Write};
+
+const HELP: &'static str = "\
+Commands, numbers are decimal or hex with 0x, an empty line repeats the last one:
+  s, step [N]           run N instructions
+  c, continue           leave the monitor and run
+  q, quit               end the emulation
+  r, regs               show PC, SP and cycles
+  stack [N]             dump N words from SP
+  x, read ADDR [N]      read N words
+  w, write ADDR VALUE   write a word
+  wb ADDR VALUE         write a byte
+  hd, hexdump ADDR [N]  dump N bytes
+  b, break [ADDR]       set a breakpoint, or list them
+  ub, unbreak ADDR      remove a breakpoint
+  watch [ADDR] [LEN] [r|w|rw] [=VALUE] [log]
+                        break on accesses to LEN bytes at ADDR, writes to the word
+                        by default, or just log them; list watchpoints without ADDR
+  unwatch ADDR          remove the watchpoints at ADDR
+  d, dis [ADDR] [N]     disassemble N instructions
+  devices               list the devices on the bus
+  reset                 reset the machine";
+
+/// What to do after a command.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Action {
+    /// Stay in the monitor.
+    Stay,
+    /// Let the machine run.
+    Continue,
+    /// End the emulation.
+    Quit,
+}
+
+#[derive(Default)]
+pub struct Monitor {
+    watch: Option<Watchpoints>,
+    /// Command an empty line repeats.
+    last: Option<String>,
+}
+
+impl Monitor {
+    pub fn new() -> Monitor {
+        Monitor::default()
+    }
+
+    /// Manage the watchpoints of a `Watch` layer on the machine's bus.
+    pub fn with_watchpoints(mut self, watch: Watchpoints) -> Monitor {
+        watch.set_enabled(false);
+        self.watch = Some(watch);
+        self
+    }
+
+    pub fn watchpoints(&self) -> Vec<Watchpoint> {
+        self.watch.as_ref().map_or(vec![], |w| w.list())
+    }
+
+    fn watch(&self) -> Result<&Watchpoints, Error> {
+        match self.watch {
+            Some(ref watch) => Ok(watch),
+            None => bail!("no watchpoints on this machine"),
+        }
+    }
+
+    /// Run at most `cycles` cycles, with watchpoints on.
+    pub fn run_for(&mut self, machine: &mut Machine, cycles: u64) -> StopReason {
+        match self.watch {
+            Some(ref watch) => {
+                watch.set_enabled(true);
+                let reason = machine.run_for(cycles);
+                watch.set_enabled(false);
+                reason
+            },
+            None => machine.run_for(cycles),
+        }
+    }
+
+    /// Read and run commands until one leaves the monitor.
+    /// The end of `input` quits.
+    pub fn repl(&mut self, machine: &mut Machine, input: &mut BufRead, out: &mut Write) -> Result<Action, Error> {
+        self.last = None;
+        show_pc(machine.cpu(), out)?;
+        loop {
+            write!(out, "(mon) ")?;
+            out.flush()?;
+            let mut line = String::new();
+            if input.read_line(&mut line)? == 0 {
+                return Ok(Action::Quit);
+            }
+            match self.command(machine, &line, out) {
+                Ok(Action::Stay) => (),
+                Ok(action) => return Ok(action),
+                Err(e) => writeln!(out, "error: {}", e)?,
+            }
+        }
+    }
+
+    /// Run a command line.
+    pub fn command(&mut self, machine: &mut Machine, line: &str, out: &mut Write) -> Result<Action, Error> {
+        let line = match (line.trim(), self.last.take()) {
+            ("", None) => return Ok(Action::Stay),
+            ("", Some(last)) => last,
+            (line, _) => line.to_string(),
+        };
+        self.last = Some(line.clone());
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (cmd, args) = (words[0], &words[1..]);
+
+        match cmd {
+            "h" | "help" | "?" => writeln!(out, "{}", HELP)?,
+            "c" | "continue" => return Ok(Action::Continue),
+            "q" | "quit" => return Ok(Action::Quit),
+            "s" | "step" => {
+                let n = arg(args, 0, 1)?;
+                if let Some(ref watch) = self.watch {
+                    watch.set_enabled(true);
+                }
+                for _ in 0..n {
+                    if let Some(reason) = machine.step() {
+                        writeln!(out, "{}", describe_stop(&reason))?;
+                        break;
+                    }
+                }
+                if let Some(ref watch) = self.watch {
+                    watch.set_enabled(false);
+                }
+                show_pc(machine.cpu(), out)?;
+            },
+            "r" | "regs" => {
+                let cpu = machine.cpu();
+                let state = match cpu.state() {
+                    CPUState::Running => "running",
+                    CPUState::Waiting => "waiting",
+                    CPUState::Sleeping => "sleeping",
+                    CPUState::Stopped => "stopped",
+                };
+                writeln!(out, "PC {:#010X}  SP {:#010X}  cycles {}  {}", cpu.pc(), cpu.sp(), machine.cycles(), state)?;
+            },
+            "stack" => {
+                let sp = machine.cpu().sp();
+                for i in 0..arg(args, 0, 8)? {
+                    let addr = sp.wrapping_add(i * 4);
+                    writeln!(out, "{:#010X}: {}", addr, word(machine.cpu(), addr))?;
+                }
+            },
+            "x" | "read" => {
+                let addr = arg(args, 0, None)? & !3;
+                let n = arg(args, 1, 1)?;
+                for row in 0..n / 4 + (n % 4 != 0) as u32 {
+                    let at = addr.wrapping_add(row * 16);
+                    let words: Vec<String> = (0..::std::cmp::min(4, n - row * 4))
+                        .map(|i| word(machine.cpu(), at.wrapping_add(i * 4)))
+                        .collect();
+                    writeln!(out, "{:#010X}: {}", at, words.join(" "))?;
+                }
+            },
+            "w" | "write" => {
+                let (addr, val) = (arg(args, 0, None)?, arg(args, 1, None)?);
+                machine.cpu_mut().bus_mut().set32be(addr as usize, val)?;
+            },
+            "wb" => {
+                let (addr, val) = (arg(args, 0, None)?, arg(args, 1, None)?);
+                machine.cpu_mut().bus_mut().set(addr as usize, val as u8)?;
+            },
+            "hd" | "hexdump" => {
+                let addr = arg(args, 0, None)?;
+                let len = arg(args, 1, 64)?;
+                for line in hexdump(machine.cpu(), addr, len) {
+                    writeln!(out, "{}", line)?;
+                }
+            },
+            "b" | "break" if args.is_empty() => {
+                let mut bps: Vec<&u32> = machine.breakpoints().iter().collect();
+                bps.sort();
+                for pc in bps {
+                    writeln!(out, "{:#010X}", pc)?;
+                }
+            },
+            "b" | "break" => machine.add_breakpoint(arg(args, 0, None)?),
+            "ub" | "unbreak" => {
+                if !machine.remove_breakpoint(arg(args, 0, None)?) {
+                    writeln!(out, "no such breakpoint")?;
+                }
+            },
+            "watch" if args.is_empty() => {
+                for wp in self.watch()?.list() {
+                    writeln!(out, "{}", describe_watchpoint(&wp))?;
+                }
+            },
+            "watch" => {
+                let wp = parse_watchpoint(args)?;
+                self.watch()?.add(wp);
+            },
+            "unwatch" => {
+                if !self.watch()?.remove(arg(args, 0, None)?) {
+                    writeln!(out, "no such watchpoint")?;
+                }
+            },
+            "d" | "dis" => {
+                let cpu = machine.cpu();
+                let addr = arg(args, 0, cpu.pc())?;
+                for (at, text) in disassemble(cpu, addr, arg(args, 1, 8)?) {
+                    let mark = if at == cpu.pc() { ">" } else if machine.breakpoints().contains(&at) { "*" } else { " " };
+                    writeln!(out, "{} {:#010X}: {}", mark, at, text)?;
+                }
+            },
+            "devices" => list_devices(machine.cpu().bus(), 0, out)?,
+            "reset" => {
+                machine.reset()?;
+                show_pc(machine.cpu(), out)?;
+            },
+            _ => bail!("unknown command: {}, try help", cmd),
+        }
+        Ok(Action::Stay)
+    }
+}
+
+/// Why the machine stopped, as a message.
+pub fn describe_stop(reason: &StopReason) -> String {
+    match *reason {
+        StopReason::Halted => "CPU halted".to_string(),
+        StopReason::Breakpoint(pc) => format!("breakpoint at {:#010X}", pc),
+        StopReason::Watchpoint(ref hit) => format!("watchpoint: {}", hit),
+        StopReason::Fault(ref e) => format!("fault: {}", e),
+        StopReason::Exit(code) => format!("guest exited with code {}", code),
+        StopReason::Limit => "limit reached".to_string(),
+        StopReason::Condition => "condition met".to_string(),
+        StopReason::Paused => "paused".to_string(),
+    }
+}
+
+/// Disassemble `n` instructions from `addr`, with their addresses.
+pub fn disassemble(cpu: &CPU, addr: u32, n: u32) -> Vec<(u32, String)> {
+    let mut res = vec![];
+    let mut at = addr;
+    for _ in 0..n {
+        match cpu.disassemble(at) {
+            Ok((text, len)) => {
+                res.push((at, text));
+                at = at.wrapping_add(len);
+            },
+            Err(_) => {
+                res.push((at, "??".to_string()));
+                at = at.wrapping_add(1);
+            },
+        }
+    }
+    res
+}
+
+/// Hexdump `len` bytes from `addr`, 16 per line, with ASCII.
+pub fn hexdump(cpu: &CPU, addr: u32, len: u32) -> Vec<String> {
+    let mut lines = vec![];
+    let mut at = addr;
+    let end = addr.wrapping_add(len);
+    while at != end {
+        let n = ::std::cmp::min(16, end.wrapping_sub(at));
+        let bytes: Vec<Option<u8>> = (0..n).map(|i| peek(cpu, at.wrapping_add(i))).collect();
+        let hex: Vec<String> = bytes.iter().map(|b| b.map_or("??".to_string(), |b| format!("{:02X}", b))).collect();
+        let ascii: String = bytes.iter().map(|b| match *b {
+            Some(b) if b >= 0x20 && b < 0x7F => b as char,
+            _ => '.',
+        }).collect();
+        lines.push(format!("{:#010X}: {:<47}  {}", at, hex.join(" "), ascii));
+        at = at.wrapping_add(n);
+    }
+    lines
+}
+
+fn show_pc(cpu: &CPU, out: &mut Write) -> Result<(), Error> {
+    let text = cpu.disassemble(cpu.pc()).map(|(text, _)| text).unwrap_or("??".to_string());
+    writeln!(out, "PC {:#010X}  SP {:#010X}  {}", cpu.pc(), cpu.sp(), text)?;
+    Ok(())
+}
+
+/// Byte at `addr`, if it can be read without side effects.
+pub fn peek(cpu: &CPU, addr: u32) -> Option<u8> {
+    cpu.bus().peek32be((addr & !3) as usize).ok().map(|w| (w >> ((3 - (addr & 3)) * 8)) as u8)
+}
+
+/// Word at `addr` in hex, peeked.
+pub fn word(cpu: &CPU, addr: u32) -> String {
+    cpu.bus().peek32be(addr as usize).map(|v| format!("{:08X}", v)).unwrap_or("????????".to_string())
+}
+
+fn list_devices(dev: &MemoryBusDevice32be, depth: usize, out: &mut Write) -> Result<(), Error> {
+    writeln!(out, "{:indent$}{}, up to {:#X}", "", dev.describe(), dev.get_size(), indent = depth * 2)?;
+    for dev in dev.devices() {
+        list_devices(dev, depth + 1, out)?;
+    }
+    Ok(())
+}
+
+/// A watchpoint on one line.
+pub fn describe_watchpoint(wp: &Watchpoint) -> String {
+    let access = match wp.access {
+        Access::Read => "r",
+        Access::Write => "w",
+        Access::Any => "rw",
+    };
+    let mut s = format!("{:#010X}-{:#010X} {}", wp.from, wp.to, access);
+    if let Some(val) = wp.value {
+        s.push_str(&format!(" ={:#X}", val));
+    }
+    if wp.action == WatchAction::Log {
+        s.push_str(" log");
+    }
+    s
+}
+
+/// Parse the arguments of `watch`.
+fn parse_watchpoint(args: &[&str]) -> Result<Watchpoint, Error> {
+    let addr = arg(args, 0, None)?;
+    let mut wp = Watchpoint::new(addr);
+    for a in &args[1..] {
+        match *a {
+            "r" => wp.access = Access::Read,
+            "w" => wp.access = Access::Write,
+            "rw" => wp.access = Access::Any,
+            "log" => wp.action = WatchAction::Log,
+            a if a.starts_with('=') => wp.value = Some(parse_num(&a[1..])?),
+            a => {
+                let len: u32 = parse_num(a)?;
+                if len == 0 {
+                    bail!("watchpoints need a length of at least 1");
+                }
+                wp.to = addr.wrapping_add(len - 1);
+            },
+        }
+    }
+    Ok(wp)
+}
+
+/// Argument `i`, or `default` if missing. A default of `None` makes it required.
+fn arg<D: Into<Option<u32>>>(args: &[&str], i: usize, default: D) -> Result<u32, Error> {
+    match (args.get(i), default.into()) {
+        (Some(s), _) => parse_num(s),
+        (None, Some(default)) => Ok(default),
+        (None, None) => bail!("missing argument, try help"),
+    }
+}
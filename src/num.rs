@@ -0,0 +1,18 @@
+//! Numbers as users write them, on the command line, in the monitor
+//! or in machine descriptions.
+
+use errors::*;
+
+use std::convert::TryFrom;
+
+/// Parse a number, hex with 0x prefix or decimal.
+/// Numbers not fitting into `T` are errors, not truncated.
+pub fn parse_num<T: TryFrom<u64>>(s: &str) -> Result<T, Error> {
+    let res = if s.starts_with("0x") || s.starts_with("0X") {
+        u64::from_str_radix(&s[2..], 16)
+    } else {
+        s.parse::<u64>()
+    };
+    let n = res.chain_err(|| format!("invalid number: {}", s))?;
+    T::try_from(n).map_err(|_| format!("number out of range: {}", s).into())
+}
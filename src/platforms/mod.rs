@@ -0,0 +1,7 @@
+//! Platforms.
+//!
+//! Whole boards, put together from CPUs and devices.
+
+pub mod phi;
+pub mod zeta;
+pub mod zpuino;
@@ -0,0 +1,30 @@
+//! Phi, the ZPU reference platform zpu-gcc's `-phi` BSP targets.
+//!
+//! Memory layout:
+//!
+//! - `0x00000000` RAM, code and data from 0, the stack at the top.
+//! - `0x080a0000` I/O page: the UART at `0x080a000c`, see `SIOTerm`,
+//!   timer and interrupt controller, see `PhiIO`.
+//!
+//! Interrupts of the I/O page make the ZPU jump to its vector at `0x20`.
+
+extern crate mem;
+
+use bus::memorybus::MemoryBusDevice32be;
+use devices::memorybus::sio::SIOTerm;
+use devices::memorybus::phi::PhiIO;
+use devices::serial::SerialBackend;
+
+pub const RAM_BASE: mem::Addr = 0x00000000;
+/// Usual RAM size, the initial SP.
+pub const RAM_SIZE: mem::Addr = 0x80000;
+pub const IO_BASE: mem::Addr = 0x080a0000;
+pub const UART: mem::Addr = 0x080a000c;
+
+/// Devices of the I/O page, to be put on the bus, with `serial` on the UART.
+pub fn io(mhz: u32, serial: Box<SerialBackend>) -> Vec<Box<MemoryBusDevice32be>> {
+    vec![
+        Box::new(SIOTerm::new_zpu(UART).with_serial(serial)),
+        Box::new(PhiIO::new(IO_BASE, mhz)),
+    ]
+}
@@ -0,0 +1,24 @@
+//! Zeta, a bare ZPU platform.
+//!
+//! Memory layout:
+//!
+//! - `0x00000000` RAM, code and data from 0, the stack at the top.
+//! - `0x80000024` UART, see `SIOTerm`.
+//!
+//! There are no timers or interrupts. For ZPUino boards, see `zpuino`.
+
+extern crate mem;
+
+use bus::memorybus::MemoryBusDevice32be;
+use devices::memorybus::sio::SIOTerm;
+use devices::serial::SerialBackend;
+
+pub const RAM_BASE: mem::Addr = 0x00000000;
+/// Usual RAM size, the initial SP.
+pub const RAM_SIZE: mem::Addr = 0x80000;
+pub const UART: mem::Addr = 0x80000024;
+
+/// Devices to be put on the bus, with `serial` on the UART.
+pub fn io(serial: Box<SerialBackend>) -> Vec<Box<MemoryBusDevice32be>> {
+    vec![Box::new(SIOTerm::new_zpu(UART).with_serial(serial))]
+}
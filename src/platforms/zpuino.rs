@@ -0,0 +1,49 @@
+//! ZPUino, the ZPU SoC of the Papilio boards.
+//!
+//! Memory layout:
+//!
+//! - `0x00000000` RAM, sketches get loaded to 0, the stack at the top.
+//! - `0x08000000` I/O slots, see `ZPUinoIO`.
+//!
+//! On the real thing a bootloader copies the sketch from SPI flash to RAM.
+//! `boot` does the same, without needing the bootloader.
+//! Interrupts make the ZPU jump to its vector at `0x20`.
+
+extern crate mem;
+
+use errors::*;
+use bus::memorybus::MemoryBusDevice32be;
+use devices::memorybus::zpuino::ZPUinoIO;
+use devices::serial::SerialBackend;
+
+pub const RAM_BASE: mem::Addr = 0x00000000;
+/// RAM of a Papilio One 500K, the initial SP.
+pub const RAM_SIZE: mem::Addr = 0x8000;
+/// Where sketches start in SPI flash on a Papilio One 500K.
+pub const SKETCH_OFFSET: usize = 0x54000;
+
+/// Devices of the I/O slots, with `flash` as contents of the SPI flash
+/// and `serial` on the UART.
+pub fn io(flash: Vec<u8>, serial: Box<SerialBackend>) -> Vec<Box<MemoryBusDevice32be>> {
+    vec![Box::new(ZPUinoIO::new(flash).with_serial(serial))]
+}
+
+/// Copy the sketch at `offset` in `flash` to `ram`, like the bootloader.
+/// Sketches start with their size in 32 bit words and a CRC16, both 16 bit big endian.
+/// The CRC isn't checked. Returns the size in bytes.
+pub fn boot(flash: &[u8], offset: usize, ram: &mut [u8]) -> Result<usize, Error> {
+    if offset + 4 > flash.len() {
+        bail!("no sketch in flash at {:#X}", offset);
+    }
+    let words = ((flash[offset] as usize) << 8) | flash[offset + 1] as usize;
+    let size = words * 4;
+    let start = offset + 4;
+    if words == 0xFFFF || start + size > flash.len() {
+        bail!("invalid sketch in flash at {:#X}", offset);
+    }
+    if size > ram.len() {
+        bail!("sketch of {:#X} bytes doesn't fit into {:#X} bytes of RAM", size, ram.len());
+    }
+    ram[..size].copy_from_slice(&flash[start..start + size]);
+    Ok(size)
+}